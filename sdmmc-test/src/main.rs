@@ -1,16 +1,15 @@
 #[macro_use]
 extern crate log;
 
-use std::slice;
-
 #[cfg(feature = "async")]
 use async_std::task;
 use clap::Parser;
-use mbr_nostd::{MasterBootRecord, PartitionTable};
+use sdmmc::bus::linux::{ChipSelect, Options};
 use sdmmc::delay::std::Delay;
-use sdmmc::SD;
+use sdmmc::partition::mbr;
+use sdmmc::{LockUnlock, SD};
 use size::Size;
-use spidev::SpidevOptions;
+use spidev::SpiModeFlags;
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
@@ -22,27 +21,66 @@ struct Args {
     verbosity: u8,
     /// Specify SPI device
     spi: String,
-    /// Specify chip-select GPIO number
-    cs: u16,
+    /// Specify chip-select GPIO number, or one of `hw`, `sysfs:<gpio>` or `cdev:<chip>:<line>`
+    #[clap(value_parser = parse_chip_select)]
+    cs: ChipSelect,
+    /// Chip-select is active high
+    #[clap(long)]
+    cs_high: bool,
+    /// SPI mode
+    #[clap(long, default_value_t = 0, value_parser = clap::value_parser!(u8).range(0..4))]
+    mode: u8,
+    /// SPI clock rate in HZ during initialization
+    #[clap(long, default_value_t = 200_000)]
+    init_speed: u32,
+    /// SPI clock rate in HZ after initialization
+    #[clap(long, default_value_t = 2_000_000)]
+    speed: u32,
+    /// Extra dummy bytes after asserting chip-select
+    #[clap(long, default_value_t = 0)]
+    dummy_bytes: usize,
+    /// Unlock card with password
+    #[clap(long)]
+    password: Option<String>,
+}
+
+fn parse_chip_select(s: &str) -> Result<ChipSelect, String> {
+    let fields: Vec<&str> = s.split(':').collect();
+    let number = |field: &str| field.parse().map_err(|e| format!("{}: {}", field, e));
+    match fields[..] {
+        ["hw"] => Ok(ChipSelect::Hardware),
+        [gpio] | ["sysfs", gpio] => Ok(ChipSelect::Sysfs(number(gpio)? as u16)),
+        ["cdev", chip, line] => Ok(ChipSelect::Cdev { chip: number(chip)?, line: number(line)? }),
+        _ => Err(format!("invalid chip-select {}", s)),
+    }
 }
 
 #[cfg_attr(not(feature = "async"), deasync::deasync)]
 async fn run(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
-    let mut bus = sdmmc::bus::linux::spi(&args.spi, args.cs)?;
+    let options = Options {
+        init_speed_hz: args.init_speed,
+        speed_hz: args.speed,
+        mode: SpiModeFlags::from_bits_truncate(args.mode as u32),
+        cs_high: args.cs_high,
+        cs: args.cs,
+        dummy_bytes: args.dummy_bytes,
+    };
+    let mut bus = sdmmc::bus::linux::spi(&args.spi, options)?;
     let card = bus.init(Delay).await?;
     debug!("Card: {:?}", card);
     let mut sd = SD::init(bus, card).await?;
+    if let (true, Some(password)) = (sd.locked(), &args.password) {
+        sd.lock_unlock(LockUnlock::Unlock(password.as_bytes())).await?;
+    }
     let num_blocks: u64 = sd.num_blocks().into();
     let size = Size::from_bytes(num_blocks * (1 << sd.block_size_shift()));
     debug!("Size {}", size);
 
-    let options = SpidevOptions { max_speed_hz: Some(2_000_000), ..Default::default() };
-    sd.bus(|bus| bus.spi(|spi| spi.0.configure(&options))).unwrap();
+    sd.bus(|bus| bus.spi(|spi| spi.accelerate()));
 
-    let mut buffer = [0u8; 512];
-    sd.read(0, slice::from_mut(&mut buffer).iter_mut()).await?;
-    let mbr = MasterBootRecord::from_bytes(&buffer).map_err(|e| format!("{:?}", e))?;
-    for partition in mbr.partition_table_entries().iter() {
+    let mut partitions = [mbr::Entry::default(); 16];
+    let count = mbr::read(&mut sd, &mut partitions).await?;
+    for partition in partitions[..count].iter() {
         println!("{:?}", partition);
     }
     Ok(())
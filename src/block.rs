@@ -0,0 +1,220 @@
+use derive_more::Display;
+use thiserror::Error;
+
+use crate::{bus, sd::BLOCK_SIZE, SD};
+
+/// Error of a layer over [`Device`], such as [`Cache`](crate::cache::Cache)
+#[derive(Debug, Error, Display)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<E> {
+    #[display("{_0}")]
+    Device(E),
+    /// Access running past the end of 32-bit block address space
+    #[display("out of range")]
+    OutOfRange,
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(error: E) -> Self {
+        Self::Device(error)
+    }
+}
+
+/// Storage addressed in blocks of `BLOCK_SIZE` bytes
+pub trait Device {
+    type Error;
+
+    fn num_blocks(&self) -> u32;
+
+    #[cfg(not(feature = "async"))]
+    fn read<'a, B>(&mut self, address: u32, blocks: B) -> Result<(), Self::Error>
+    where
+        B: core::iter::ExactSizeIterator<Item = &'a mut [u8; BLOCK_SIZE]>;
+    #[cfg(not(feature = "async"))]
+    fn write<'a, B>(&mut self, address: u32, blocks: B) -> Result<(), Self::Error>
+    where
+        B: core::iter::ExactSizeIterator<Item = &'a [u8; BLOCK_SIZE]>;
+
+    #[cfg(feature = "async")]
+    fn read<'a, B>(
+        &mut self,
+        address: u32,
+        blocks: B,
+    ) -> impl Future<Output = Result<(), Self::Error>>
+    where
+        B: core::iter::ExactSizeIterator<Item = &'a mut [u8; BLOCK_SIZE]>;
+    #[cfg(feature = "async")]
+    fn write<'a, B>(
+        &mut self,
+        address: u32,
+        blocks: B,
+    ) -> impl Future<Output = Result<(), Self::Error>>
+    where
+        B: core::iter::ExactSizeIterator<Item = &'a [u8; BLOCK_SIZE]>;
+}
+
+#[cfg_attr(not(feature = "async"), deasync::deasync)]
+impl<T: Device> Device for &mut T {
+    type Error = T::Error;
+
+    fn num_blocks(&self) -> u32 {
+        T::num_blocks(self)
+    }
+
+    async fn read<'a, B>(&mut self, address: u32, blocks: B) -> Result<(), T::Error>
+    where
+        B: core::iter::ExactSizeIterator<Item = &'a mut [u8; BLOCK_SIZE]>,
+    {
+        T::read(self, address, blocks).await
+    }
+
+    async fn write<'a, B>(&mut self, address: u32, blocks: B) -> Result<(), T::Error>
+    where
+        B: core::iter::ExactSizeIterator<Item = &'a [u8; BLOCK_SIZE]>,
+    {
+        T::write(self, address, blocks).await
+    }
+}
+
+#[cfg_attr(not(feature = "async"), deasync::deasync)]
+impl<E, BUS> Device for SD<BUS>
+where
+    BUS: bus::Read<Error = E> + bus::Write<Error = E> + bus::Bus<Error = E>,
+{
+    type Error = bus::Error<E>;
+
+    fn num_blocks(&self) -> u32 {
+        (self.csd.capacity() / BLOCK_SIZE as u64) as u32
+    }
+
+    async fn read<'a, B>(&mut self, address: u32, blocks: B) -> Result<(), bus::Error<E>>
+    where
+        B: core::iter::ExactSizeIterator<Item = &'a mut [u8; BLOCK_SIZE]>,
+    {
+        SD::read(self, address, blocks).await
+    }
+
+    async fn write<'a, B>(&mut self, address: u32, blocks: B) -> Result<(), bus::Error<E>>
+    where
+        B: core::iter::ExactSizeIterator<Item = &'a [u8; BLOCK_SIZE]>,
+    {
+        SD::write(self, address, blocks).await
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::{Device, BLOCK_SIZE};
+
+    pub struct Memory<const N: usize> {
+        pub blocks: [[u8; BLOCK_SIZE]; N],
+        /// Number of read and write calls
+        pub reads: usize,
+        pub writes: usize,
+        /// Fails every write if set
+        pub read_only: bool,
+    }
+
+    impl<const N: usize> Default for Memory<N> {
+        fn default() -> Self {
+            Self { blocks: [[0; BLOCK_SIZE]; N], reads: 0, writes: 0, read_only: false }
+        }
+    }
+
+    #[cfg_attr(not(feature = "async"), deasync::deasync)]
+    impl<const N: usize> Device for Memory<N> {
+        type Error = ();
+
+        fn num_blocks(&self) -> u32 {
+            N as u32
+        }
+
+        async fn read<'a, B>(&mut self, address: u32, blocks: B) -> Result<(), ()>
+        where
+            B: core::iter::ExactSizeIterator<Item = &'a mut [u8; BLOCK_SIZE]>,
+        {
+            let range = address as usize..address as usize + blocks.len();
+            let source = self.blocks.get(range).ok_or(())?;
+            self.reads += 1;
+            blocks.zip(source).for_each(|(block, source)| block.copy_from_slice(source));
+            Ok(())
+        }
+
+        async fn write<'a, B>(&mut self, address: u32, blocks: B) -> Result<(), ()>
+        where
+            B: core::iter::ExactSizeIterator<Item = &'a [u8; BLOCK_SIZE]>,
+        {
+            let range = address as usize..address as usize + blocks.len();
+            let target = self.blocks.get_mut(range).filter(|_| !self.read_only).ok_or(())?;
+            self.writes += 1;
+            target.iter_mut().zip(blocks).for_each(|(target, block)| target.copy_from_slice(block));
+            Ok(())
+        }
+    }
+
+    /// Large device keeping only blocks written with non-zero data
+    pub struct Sparse {
+        num_blocks: u32,
+        blocks: std::collections::BTreeMap<u32, [u8; BLOCK_SIZE]>,
+    }
+
+    impl Sparse {
+        pub fn new(num_blocks: u32) -> Self {
+            Self { num_blocks, blocks: Default::default() }
+        }
+
+        pub fn block(&self, address: u32) -> [u8; BLOCK_SIZE] {
+            self.blocks.get(&address).copied().unwrap_or([0; BLOCK_SIZE])
+        }
+    }
+
+    #[cfg_attr(not(feature = "async"), deasync::deasync)]
+    impl Device for Sparse {
+        type Error = ();
+
+        fn num_blocks(&self) -> u32 {
+            self.num_blocks
+        }
+
+        async fn read<'a, B>(&mut self, address: u32, blocks: B) -> Result<(), ()>
+        where
+            B: core::iter::ExactSizeIterator<Item = &'a mut [u8; BLOCK_SIZE]>,
+        {
+            if address as u64 + blocks.len() as u64 > self.num_blocks as u64 {
+                return Err(());
+            }
+            blocks.zip(address..).for_each(|(block, address)| *block = self.block(address));
+            Ok(())
+        }
+
+        async fn write<'a, B>(&mut self, address: u32, blocks: B) -> Result<(), ()>
+        where
+            B: core::iter::ExactSizeIterator<Item = &'a [u8; BLOCK_SIZE]>,
+        {
+            if address as u64 + blocks.len() as u64 > self.num_blocks as u64 {
+                return Err(());
+            }
+            for (block, address) in blocks.zip(address..) {
+                match block.iter().all(|&byte| byte == 0) {
+                    true => self.blocks.remove(&address),
+                    false => self.blocks.insert(address, *block),
+                };
+            }
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "async")]
+    pub fn block_on<F: Future>(future: F) -> F::Output {
+        use core::task::{Context, Poll, Waker};
+        match core::pin::pin!(future).poll(&mut Context::from_waker(Waker::noop())) {
+            Poll::Ready(output) => output,
+            Poll::Pending => unreachable!(),
+        }
+    }
+
+    #[cfg(not(feature = "async"))]
+    pub fn block_on<T>(output: T) -> T {
+        output
+    }
+}
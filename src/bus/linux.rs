@@ -1,31 +1,224 @@
-use std::{io, time};
+use std::{
+    io,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time,
+};
 
 use derive_more::Display;
 use gpio::{sysfs::SysFsGpioOutput, GpioOut};
+use gpio_cdev::{Chip, LineHandle, LineRequestFlags};
 use spidev::{SpiModeFlags, Spidev, SpidevOptions, SpidevTransfer};
 use thiserror::Error;
 
 use crate::bus::spi;
 
-pub struct SPI(pub Spidev);
+/// Write-only bytes are queued until the next read or chip-select change,
+/// so that a command or a whole data block goes out in a single `SPI_IOC_MESSAGE`
+const QUEUE_SIZE: usize = 1024;
+/// Clocked out while reading, longer reads are split into several transfers
+const IDLE: [u8; 512] = [0xFF; 512];
+
+#[derive(Copy, Clone, Debug, Default)]
+pub enum ChipSelect {
+    /// GPIO number exported through `/sys/class/gpio`
+    Sysfs(u16),
+    /// Line `line` of `/dev/gpiochip{chip}`
+    Cdev { chip: u32, line: u32 },
+    /// Chip select driven by the spidev controller itself
+    #[default]
+    Hardware,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Options {
+    /// Clock rate during card initialization, between 100KHz and 400KHz
+    pub init_speed_hz: u32,
+    /// Clock rate after [`SPI::accelerate`]
+    pub speed_hz: u32,
+    /// One of `SPI_MODE_0` to `SPI_MODE_3`
+    pub mode: SpiModeFlags,
+    /// Chip select is active high
+    pub cs_high: bool,
+    /// GPIO or controller driving chip select
+    pub cs: ChipSelect,
+    /// Extra 0xFF bytes clocked right after asserting chip select
+    pub dummy_bytes: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            init_speed_hz: 200_000,
+            speed_hz: 2_000_000,
+            mode: SpiModeFlags::SPI_MODE_0,
+            cs_high: false,
+            cs: ChipSelect::default(),
+            dummy_bytes: 0,
+        }
+    }
+}
+
+enum Select {
+    Sysfs(SysFsGpioOutput),
+    Cdev(LineHandle),
+    Hardware,
+}
+
+/// Issues `SPI_IOC_MESSAGE` ioctls on behalf of [`Transport`]
+trait Device {
+    fn set_mode(&mut self, mode: SpiModeFlags) -> io::Result<()>;
+
+    /// Writes `tx` then reads at most `IDLE.len()` bytes into `rx` in a single message
+    /// at `speed` Hz, keeping chip select asserted afterwards if `hold`
+    fn message(&mut self, tx: &[u8], rx: &mut [u8], speed: u32, hold: bool) -> io::Result<()>;
+}
+
+impl Device for Spidev {
+    fn set_mode(&mut self, mode: SpiModeFlags) -> io::Result<()> {
+        self.configure(&SpidevOptions::new().mode(mode).build())
+    }
+
+    fn message(&mut self, tx: &[u8], rx: &mut [u8], speed: u32, hold: bool) -> io::Result<()> {
+        let mut transfers: [SpidevTransfer; 2] = Default::default();
+        let mut num_transfers = 0;
+        if !tx.is_empty() || rx.is_empty() {
+            transfers[0] = SpidevTransfer::write(tx);
+            num_transfers = 1;
+        }
+        if !rx.is_empty() {
+            transfers[num_transfers] = SpidevTransfer::read_write(&IDLE[..rx.len()], rx);
+            num_transfers += 1;
+        }
+        transfers.iter_mut().for_each(|transfer| transfer.speed_hz = speed);
+        transfers[num_transfers - 1].cs_change = hold as u8;
+        self.transfer_multiple(&mut transfers[..num_transfers])
+    }
+}
+
+struct Transport<D = Spidev> {
+    device: D,
+    options: Options,
+    mode: SpiModeFlags,
+    speed_hz: u32,
+    select: Select,
+    selected: bool,
+    queue: [u8; QUEUE_SIZE],
+    queued: usize,
+}
+
+impl<D: Device> Transport<D> {
+    fn hardware(&self) -> bool {
+        matches!(self.select, Select::Hardware)
+    }
+
+    fn push(&mut self, tx: &[u8]) -> io::Result<()> {
+        for chunk in tx.chunks(QUEUE_SIZE) {
+            if self.queued + chunk.len() > QUEUE_SIZE {
+                self.flush(&mut [])?;
+            }
+            self.queue[self.queued..self.queued + chunk.len()].copy_from_slice(chunk);
+            self.queued += chunk.len();
+        }
+        Ok(())
+    }
+
+    /// Sends queued bytes followed by reading `rx`
+    fn flush(&mut self, rx: &mut [u8]) -> io::Result<()> {
+        // Keep hardware chip select asserted between messages while selected
+        self.send(rx, self.hardware() && self.selected)
+    }
+
+    fn send(&mut self, rx: &mut [u8], hold: bool) -> io::Result<()> {
+        let queued = core::mem::take(&mut self.queued);
+        // Bytes clocked while deselected need chip select inactive, which the controller
+        // can only do by temporarily inverting its polarity, same as linux mmc_spi does
+        let invert = self.hardware() && !self.selected;
+        if invert {
+            self.device.set_mode(self.mode ^ SpiModeFlags::SPI_CS_HIGH)?;
+        }
+        let result = self.transfer(queued, rx, hold);
+        if invert {
+            self.device.set_mode(self.mode)?;
+        }
+        result
+    }
+
+    fn transfer(&mut self, queued: usize, rx: &mut [u8], hold: bool) -> io::Result<()> {
+        let mut chunks = rx.chunks_mut(IDLE.len());
+        let first = chunks.next().unwrap_or(&mut []);
+        self.device.message(&self.queue[..queued], first, self.speed_hz, hold)?;
+        for chunk in chunks {
+            self.device.message(&[], chunk, self.speed_hz, hold)?;
+        }
+        Ok(())
+    }
+
+    fn set_selected(&mut self, selected: bool) -> io::Result<()> {
+        let changed = selected != self.selected;
+        match self.select {
+            Select::Sysfs(_) | Select::Cdev(_) if self.queued > 0 => self.flush(&mut [])?,
+            Select::Sysfs(_) | Select::Cdev(_) => (),
+            // Hardware chip select is released at the end of a message
+            Select::Hardware if changed && !selected => self.send(&mut [], false)?,
+            // Bytes queued while deselected must not be clocked with chip select asserted
+            Select::Hardware if changed && self.queued > 0 => self.flush(&mut [])?,
+            Select::Hardware => (),
+        }
+        let level = selected == self.options.cs_high;
+        match self.select {
+            Select::Sysfs(ref mut gpio) => gpio.set_value(level)?,
+            Select::Cdev(ref line) => line.set_value(level as u8).map_err(io::Error::other)?,
+            Select::Hardware => (),
+        }
+        self.selected = selected;
+        if changed && selected {
+            for _ in 0..self.options.dummy_bytes {
+                self.push(&[0xFF])?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Shared by [`SPI`] and [`CS`], which may be moved to another thread together
+#[derive(Clone)]
+struct Shared(Arc<Mutex<Transport>>);
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Transport> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+pub struct SPI(Shared);
 
 #[cfg_attr(not(feature = "async"), deasync::deasync)]
 impl spi::Transfer for SPI {
     type Error = io::Error;
 
     async fn transfer(&mut self, tx: &[u8], rx: &mut [u8]) -> io::Result<()> {
-        let mut buf = Vec::with_capacity(std::cmp::max(tx.len(), rx.len()));
-        if tx.len() > 0 {
-            buf.resize(tx.len(), 0);
-            self.0.transfer(&mut SpidevTransfer::read_write(tx, &mut buf))
-        } else {
-            buf.resize(rx.len(), 0xFF);
-            self.0.transfer(&mut SpidevTransfer::read_write(&buf, rx))
+        let mut transport = self.0.lock();
+        transport.push(tx)?;
+        match rx.is_empty() {
+            true => Ok(()),
+            false => transport.flush(rx),
         }
     }
 }
 
-pub struct GPIO(SysFsGpioOutput);
+impl SPI {
+    /// Switch to operating clock rate, once card initialized
+    pub fn accelerate(&mut self) {
+        let mut transport = self.0.lock();
+        transport.speed_hz = transport.options.speed_hz;
+    }
+
+    pub fn set_speed_hz(&mut self, speed_hz: u32) {
+        self.0.lock().speed_hz = speed_hz;
+    }
+}
+
+pub struct CS(Shared);
 
 #[derive(Debug, Display, Error)]
 pub struct IOError(#[from] io::Error);
@@ -36,27 +229,17 @@ impl embedded_hal::digital::Error for IOError {
     }
 }
 
-impl embedded_hal::digital::ErrorType for GPIO {
+impl embedded_hal::digital::ErrorType for CS {
     type Error = IOError;
 }
 
-impl embedded_hal::digital::OutputPin for GPIO {
+impl embedded_hal::digital::OutputPin for CS {
     fn set_high(&mut self) -> Result<(), IOError> {
-        Ok(self.0.set_value(true)?)
+        Ok(self.0.lock().set_selected(false)?)
     }
 
     fn set_low(&mut self) -> Result<(), IOError> {
-        Ok(self.0.set_value(false)?)
-    }
-}
-
-impl SPI {
-    pub fn new(spi: &str) -> io::Result<Self> {
-        let mut spi = Spidev::open(spi)?;
-        let mode = SpiModeFlags::SPI_MODE_0;
-        let mut builder = SpidevOptions::new();
-        spi.configure(&builder.bits_per_word(8).max_speed_hz(200_000).mode(mode).build())?;
-        Ok(Self(spi))
+        Ok(self.0.lock().set_selected(true)?)
     }
 }
 
@@ -71,8 +254,127 @@ impl embedded_timers::clock::Clock for SystemClock {
     }
 }
 
-pub fn spi(spi: &str, cs: u16) -> io::Result<spi::Bus<SPI, GPIO, SystemClock>> {
-    let spi = SPI::new(spi)?;
-    let cs = gpio::sysfs::SysFsGpioOutput::open(cs)?;
-    Ok(spi::Bus::new(spi, GPIO(cs), SystemClock {}))
+pub fn spi(spi: &str, options: Options) -> io::Result<spi::Bus<SPI, CS, SystemClock>> {
+    let mut device = Spidev::open(spi)?;
+    let mut mode = options.mode;
+    let select = match options.cs {
+        ChipSelect::Sysfs(number) => Select::Sysfs(SysFsGpioOutput::open(number)?),
+        ChipSelect::Cdev { chip, line } => {
+            let mut chip = Chip::new(format!("/dev/gpiochip{}", chip)).map_err(io::Error::other)?;
+            let line = chip.get_line(line).map_err(io::Error::other)?;
+            let flags = LineRequestFlags::OUTPUT;
+            let level = !options.cs_high as u8;
+            let handle = line.request(flags, level, "sdmmc-cs").map_err(io::Error::other)?;
+            Select::Cdev(handle)
+        }
+        ChipSelect::Hardware => Select::Hardware,
+    };
+    match select {
+        Select::Hardware if options.cs_high => mode |= SpiModeFlags::SPI_CS_HIGH,
+        Select::Hardware => (),
+        _ => mode |= SpiModeFlags::SPI_NO_CS,
+    }
+    let mut builder = SpidevOptions::new();
+    // Clock rate is given per transfer, which the controller caps at its maximum
+    let max_speed_hz = options.speed_hz.max(options.init_speed_hz);
+    device.configure(&builder.bits_per_word(8).max_speed_hz(max_speed_hz).mode(mode).build())?;
+    let speed_hz = options.init_speed_hz;
+    let queue = [0; QUEUE_SIZE];
+    let selected = false;
+    let transport =
+        Transport { device, options, mode, speed_hz, select, selected, queue, queued: 0 };
+    let transport = Shared(Arc::new(Mutex::new(transport)));
+    Ok(spi::Bus::new(SPI(transport.clone()), CS(transport), SystemClock {}))
+}
+
+#[cfg(test)]
+mod test {
+    use spidev::SpiModeFlags;
+
+    use super::{Device, Options, Select, Transport, QUEUE_SIZE};
+
+    #[derive(Debug, PartialEq)]
+    struct Message {
+        tx: Vec<u8>,
+        rx: usize,
+        hold: bool,
+        mode: SpiModeFlags,
+    }
+
+    struct Mock {
+        mode: SpiModeFlags,
+        messages: Vec<Message>,
+    }
+
+    impl Device for Mock {
+        fn set_mode(&mut self, mode: SpiModeFlags) -> std::io::Result<()> {
+            self.mode = mode;
+            Ok(())
+        }
+
+        fn message(&mut self, tx: &[u8], rx: &mut [u8], _: u32, hold: bool) -> std::io::Result<()> {
+            rx.fill(0xFF);
+            let (tx, rx, mode) = (tx.to_vec(), rx.len(), self.mode);
+            self.messages.push(Message { tx, rx, hold, mode });
+            Ok(())
+        }
+    }
+
+    fn hardware() -> Transport<Mock> {
+        let (mode, select, queue) = (SpiModeFlags::SPI_MODE_0, Select::Hardware, [0; QUEUE_SIZE]);
+        let device = Mock { mode, messages: vec![] };
+        let (options, speed_hz, selected) = (Options::default(), 200_000, false);
+        Transport { device, options, mode, speed_hz, select, selected, queue, queued: 0 }
+    }
+
+    fn message(tx: &[u8], rx: usize, hold: bool) -> Message {
+        Message { tx: tx.to_vec(), rx, hold, mode: SpiModeFlags::SPI_MODE_0 }
+    }
+
+    #[test]
+    fn test_send() {
+        fn send<T: Send>() {}
+        send::<super::SPI>();
+        send::<super::CS>();
+    }
+
+    #[test]
+    fn test_queue() {
+        let mut transport = hardware();
+        transport.set_selected(true).unwrap();
+        transport.push(&[0x51, 0, 0, 0, 0, 0xFF]).unwrap();
+        transport.flush(&mut [0; 1]).unwrap();
+        // Queue overflows into a write-only message, long reads split by `IDLE` size
+        transport.push(&[0xA5; 1500]).unwrap();
+        transport.flush(&mut [0; 600]).unwrap();
+        let expected = [
+            message(&[0x51, 0, 0, 0, 0, 0xFF], 1, true),
+            message(&[0xA5; QUEUE_SIZE], 0, true),
+            message(&[0xA5; 1500 - QUEUE_SIZE], 512, true),
+            message(&[], 88, true),
+        ];
+        assert_eq!(transport.device.messages, expected);
+    }
+
+    #[test]
+    fn test_hold() {
+        let mut transport = hardware();
+        transport.push(&[0xFF; 10]).unwrap();
+        transport.set_selected(true).unwrap();
+        transport.push(&[0x40, 0, 0, 0, 0, 0x95]).unwrap();
+        transport.flush(&mut [0; 1]).unwrap();
+        transport.set_selected(true).unwrap();
+        transport.set_selected(false).unwrap();
+        transport.flush(&mut [0; 1]).unwrap();
+        let inverted = SpiModeFlags::SPI_MODE_0 | SpiModeFlags::SPI_CS_HIGH;
+        let expected = [
+            // Clocked while deselected, with chip select polarity inverted
+            Message { tx: vec![0xFF; 10], rx: 0, hold: false, mode: inverted },
+            message(&[0x40, 0, 0, 0, 0, 0x95], 1, true),
+            message(&[], 0, false),
+            Message { tx: vec![], rx: 1, hold: false, mode: inverted },
+        ];
+        assert_eq!(transport.device.messages, expected);
+        assert_eq!(transport.device.mode, SpiModeFlags::SPI_MODE_0);
+    }
 }
@@ -2,13 +2,22 @@
 pub mod linux;
 pub mod spi;
 
+use core::time::Duration;
+
 use derive_more::Display;
 use thiserror::Error;
 
-use crate::sd::{registers::CSD, response::R1Status, transfer, BLOCK_SIZE};
+use crate::sd::{
+    command::LockUnlock,
+    registers::{SDStatus, CSD},
+    response::R2,
+    response::{R1Status, R1},
+    transfer, BLOCK_SIZE,
+};
 
 #[derive(Debug, Error, Display)]
-pub enum Error<BUS> {
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ErrorKind<BUS> {
     #[display("bus error: {_0}")]
     BUS(BUS),
     /// Probably no card
@@ -17,27 +26,319 @@ pub enum Error<BUS> {
     #[display("not idle")]
     NotIdle,
     #[display("command error: {_0}")]
-    Command(#[from] R1Status),
+    /// All flags of R1 with at least one error
+    Command(#[from] R1),
     #[display("transfer error: {_0}")]
     Transfer(#[from] transfer::TokenError),
     /// No respond within expected duration
     #[display("timeout error")]
     Timeout,
-    #[display("generic error")]
-    Generic,
+    /// Card locked by password, unlock with [`Write::lock_unlock`]
+    #[display("card locked")]
+    Locked,
+    /// Wrong password, or operation not allowed in current lock state
+    #[display("lock/unlock failed")]
+    LockUnlockFailed,
+    /// Password of LOCK_UNLOCK longer than 16 bytes
+    #[display("password too long")]
+    PasswordTooLong,
+    /// Voltage not accepted or check pattern mismatch in R7
+    #[display("interface condition rejected")]
+    InterfaceCondition,
+    /// Card still idle after initialization retries
+    #[display("initialization timeout")]
+    InitTimeout,
+    /// Token other than start block token where data expected
+    #[display("unexpected token {_0:#04X}")]
+    UnexpectedToken(u8),
+    #[display("invalid CSD")]
+    InvalidCSD,
+    /// Data response token not recognized
+    #[display("invalid data response {_0:#04X}")]
+    InvalidDataResponse(u8),
+    /// Data block rejected for CRC mismatch
+    #[display("CRC error")]
+    CRC,
+    /// Data block rejected for write error
+    #[display("write error")]
+    WriteError,
+    /// Operation not implemented by bus
+    #[display("unsupported")]
+    Unsupported,
+}
+
+impl<BUS> ErrorKind<BUS> {
+    /// Outcome of failed operation in spans
+    #[cfg(feature = "tracing")]
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::BUS(_) => "bus",
+            Self::NoResponse => "no_response",
+            Self::NotIdle => "not_idle",
+            Self::Command(_) => "command",
+            Self::Transfer(_) => "transfer",
+            Self::Timeout => "timeout",
+            Self::Locked => "locked",
+            Self::LockUnlockFailed => "lock_unlock_failed",
+            Self::PasswordTooLong => "password_too_long",
+            Self::InterfaceCondition => "interface_condition",
+            Self::InitTimeout => "init_timeout",
+            Self::UnexpectedToken(_) => "unexpected_token",
+            Self::InvalidCSD => "invalid_csd",
+            Self::InvalidDataResponse(_) => "invalid_data_response",
+            Self::CRC => "crc",
+            Self::WriteError => "write_error",
+            Self::Unsupported => "unsupported",
+        }
+    }
+}
+
+/// Stage of command or data transfer where an error occurred
+#[derive(Copy, Clone, Debug, PartialEq, Display)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Phase {
+    #[display("command")]
+    Command,
+    #[display("response")]
+    Response,
+    #[display("data token")]
+    DataToken,
+    #[display("data")]
+    Data,
+    #[display("busy wait")]
+    Busy,
+    #[display("CRC")]
+    CRC,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Error<BUS> {
+    kind: ErrorKind<BUS>,
+    command: Option<u8>,
+    phase: Option<Phase>,
+    address: Option<u32>,
+    completed: Option<u32>,
+    status: Option<R2>,
+    unrecovered: bool,
+}
+
+impl<BUS> Error<BUS> {
+    pub fn kind(&self) -> &ErrorKind<BUS> {
+        &self.kind
+    }
+
+    pub fn into_kind(self) -> ErrorKind<BUS> {
+        self.kind
+    }
+
+    /// Index of command in progress, `None` if failed outside a command
+    pub fn command(&self) -> Option<u8> {
+        self.command
+    }
+
+    pub fn phase(&self) -> Option<Phase> {
+        self.phase
+    }
+
+    /// Block address of failed block, or of the operation if not a data transfer
+    pub fn address(&self) -> Option<u32> {
+        self.address
+    }
+
+    /// Number of blocks transferred without error before a read or write failed,
+    /// `None` if unknown or not a data transfer
+    pub fn completed(&self) -> Option<u32> {
+        self.completed
+    }
+
+    /// Context is recorded where the error is detected, outer layers won't override it
+    pub(crate) fn during(self, command: u8) -> Self {
+        Self { command: self.command.or(Some(command)), ..self }
+    }
+
+    pub(crate) fn at(self, phase: Phase) -> Self {
+        Self { phase: self.phase.or(Some(phase)), ..self }
+    }
+
+    pub(crate) fn with_address(self, address: u32) -> Self {
+        Self { address: self.address.or(Some(address)), ..self }
+    }
+
+    /// Card status read with SEND_STATUS when recovering from the error
+    pub fn status(&self) -> Option<R2> {
+        self.status
+    }
+
+    pub(crate) fn with_completed(self, completed: Option<u32>) -> Self {
+        Self { completed, ..self }
+    }
+
+    /// `None` if card didn't come back to transfer state
+    pub(crate) fn with_status(self, status: Option<R2>) -> Self {
+        Self { status, unrecovered: status.is_none(), ..self }
+    }
+}
+
+impl<BUS> From<ErrorKind<BUS>> for Error<BUS> {
+    fn from(kind: ErrorKind<BUS>) -> Self {
+        let (command, phase, address, completed) = (None, None, None, None);
+        Self { kind, command, phase, address, completed, status: None, unrecovered: false }
+    }
+}
+
+impl<BUS> From<R1> for Error<BUS> {
+    fn from(r1: R1) -> Self {
+        ErrorKind::Command(r1).into()
+    }
+}
+
+impl<BUS> From<transfer::TokenError> for Error<BUS> {
+    fn from(error: transfer::TokenError) -> Self {
+        ErrorKind::Transfer(error).into()
+    }
+}
+
+impl<BUS: core::fmt::Display> core::fmt::Display for Error<BUS> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.kind)?;
+        if let Some(phase) = self.phase {
+            write!(f, " in {}", phase)?;
+        }
+        if let Some(command) = self.command {
+            write!(f, " of CMD{}", command)?;
+        }
+        if let Some(address) = self.address {
+            write!(f, " at block {}", address)?;
+        }
+        if let Some(completed) = self.completed {
+            write!(f, " after {} blocks", completed)?;
+        }
+        Ok(())
+    }
+}
+
+impl<BUS: core::fmt::Debug + core::fmt::Display> core::error::Error for Error<BUS> {}
+
+/// Decides between retrying, re-initializing or giving up on a failure
+pub trait Classify {
+    /// Transient failure, the same operation may succeed if retried
+    fn retryable(&self) -> bool;
+    /// Card in unknown state, init again before further operations
+    fn needs_reinit(&self) -> bool;
+    /// Card removed or not powered
+    fn card_gone(&self) -> bool;
+    /// Storage medium or card controller failed, retrying won't help
+    fn media_error(&self) -> bool;
+    /// Card locked by password
+    fn locked(&self) -> bool;
+}
+
+impl<BUS: Classify> Classify for Error<BUS> {
+    fn retryable(&self) -> bool {
+        if self.needs_reinit() || self.media_error() || self.locked() {
+            return false;
+        }
+        match &self.kind {
+            ErrorKind::BUS(e) => e.retryable(),
+            ErrorKind::Command(r1) => r1.has(R1Status::CommandCRCError),
+            ErrorKind::Transfer(e) => matches!(e, transfer::TokenError::Generic),
+            ErrorKind::NoResponse | ErrorKind::Timeout | ErrorKind::CRC => true,
+            ErrorKind::InvalidCSD => true,
+            ErrorKind::UnexpectedToken(_) | ErrorKind::InvalidDataResponse(_) => true,
+            _ => false,
+        }
+    }
+
+    fn needs_reinit(&self) -> bool {
+        self.unrecovered
+            || match &self.kind {
+                ErrorKind::BUS(e) => e.needs_reinit(),
+                ErrorKind::NoResponse => self.status.is_none(),
+                ErrorKind::NotIdle => true,
+                ErrorKind::InterfaceCondition | ErrorKind::InitTimeout => true,
+                // Card went through power cycle
+                ErrorKind::Command(r1) => r1.has(R1Status::InIdleState),
+                _ => false,
+            }
+    }
+
+    fn card_gone(&self) -> bool {
+        match &self.kind {
+            ErrorKind::BUS(e) => e.card_gone(),
+            // Card still there if it responds when recovering
+            ErrorKind::NoResponse => self.status.is_none(),
+            _ => false,
+        }
+    }
+
+    fn media_error(&self) -> bool {
+        let status = |f: fn(R2) -> bool| self.status.is_some_and(f);
+        if status(|s| s.card_ecc_failed() || s.card_controller_error()) {
+            return true;
+        }
+        match &self.kind {
+            ErrorKind::BUS(e) => e.media_error(),
+            ErrorKind::Transfer(transfer::TokenError::CardECC | transfer::TokenError::CC) => true,
+            ErrorKind::WriteError => !status(|s| s.write_protect_violation()),
+            _ => false,
+        }
+    }
+
+    fn locked(&self) -> bool {
+        self.status.is_some_and(|s| s.card_locked())
+            || match &self.kind {
+                ErrorKind::BUS(e) => e.locked(),
+                ErrorKind::Locked | ErrorKind::Transfer(transfer::TokenError::CardLocked) => true,
+                _ => false,
+            }
+    }
+}
+
+/// Bus activity accumulated for [`Statistics`](crate::stats::Statistics)
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Activity {
+    pub commands: u32,
+    /// Time spent waiting while card busy
+    pub busy: Duration,
 }
 
 pub trait Bus {
     type Error;
     fn before(&mut self) -> Result<(), Error<Self::Error>>;
     fn after(&mut self) -> Result<(), Error<Self::Error>>;
+    /// Time since creation by bus clock, for latencies of statistics,
+    /// none unless implemented
+    fn uptime(&self) -> Option<Duration> {
+        None
+    }
+    /// Activity since last call, none unless implemented
+    fn take_activity(&mut self) -> Activity {
+        Activity::default()
+    }
 }
 
+/// Operations other than reading blocks and CSD fail with [`ErrorKind::Unsupported`]
+/// unless implemented
 pub trait Read {
     type Error;
     #[cfg(not(feature = "async"))]
     fn read_csd(&mut self) -> Result<CSD, Error<Self::Error>>;
     #[cfg(not(feature = "async"))]
+    fn read_sd_status(&mut self) -> Result<SDStatus, Error<Self::Error>> {
+        Err(ErrorKind::Unsupported.into())
+    }
+    #[cfg(not(feature = "async"))]
+    fn read_status(&mut self) -> Result<R2, Error<Self::Error>> {
+        Err(ErrorKind::Unsupported.into())
+    }
+    /// Write protection of 32 groups from `address`, bit 0 for the first group
+    #[cfg(not(feature = "async"))]
+    fn read_write_protect(&mut self, _address: u32) -> Result<u32, Error<Self::Error>> {
+        Err(ErrorKind::Unsupported.into())
+    }
+    #[cfg(not(feature = "async"))]
     fn read<'a, B>(&mut self, block: u32, blocks: B) -> Result<(), Error<Self::Error>>
     where
         B: core::iter::ExactSizeIterator<Item = &'a mut [u8; BLOCK_SIZE]>;
@@ -45,6 +346,21 @@ pub trait Read {
     #[cfg(feature = "async")]
     fn read_csd(&mut self) -> impl Future<Output = Result<CSD, Error<Self::Error>>>;
     #[cfg(feature = "async")]
+    fn read_sd_status(&mut self) -> impl Future<Output = Result<SDStatus, Error<Self::Error>>> {
+        async { Err(ErrorKind::Unsupported.into()) }
+    }
+    #[cfg(feature = "async")]
+    fn read_status(&mut self) -> impl Future<Output = Result<R2, Error<Self::Error>>> {
+        async { Err(ErrorKind::Unsupported.into()) }
+    }
+    #[cfg(feature = "async")]
+    fn read_write_protect(
+        &mut self,
+        _address: u32,
+    ) -> impl Future<Output = Result<u32, Error<Self::Error>>> {
+        async { Err(ErrorKind::Unsupported.into()) }
+    }
+    #[cfg(feature = "async")]
     fn read<'a, B>(
         &mut self,
         block: u32,
@@ -54,12 +370,32 @@ pub trait Read {
         B: core::iter::ExactSizeIterator<Item = &'a mut [u8; BLOCK_SIZE]>;
 }
 
+/// Operations other than writing blocks fail with [`ErrorKind::Unsupported`]
+/// unless implemented
 pub trait Write {
     type Error;
     #[cfg(not(feature = "async"))]
     fn write<'a, B>(&mut self, block: u32, blocks: B) -> Result<(), Error<Self::Error>>
     where
         B: core::iter::ExactSizeIterator<Item = &'a [u8; BLOCK_SIZE]>;
+    #[cfg(not(feature = "async"))]
+    fn lock_unlock(&mut self, _command: LockUnlock<'_>) -> Result<(), Error<Self::Error>> {
+        Err(ErrorKind::Unsupported.into())
+    }
+    #[cfg(not(feature = "async"))]
+    fn program_csd(&mut self, _csd: CSD) -> Result<(), Error<Self::Error>> {
+        Err(ErrorKind::Unsupported.into())
+    }
+    /// Sets or clears write protection of group containing `address`
+    #[cfg(not(feature = "async"))]
+    fn set_write_protect(
+        &mut self,
+        _address: u32,
+        _protect: bool,
+    ) -> Result<(), Error<Self::Error>> {
+        Err(ErrorKind::Unsupported.into())
+    }
+
     #[cfg(feature = "async")]
     fn write<'a, B>(
         &mut self,
@@ -68,4 +404,50 @@ pub trait Write {
     ) -> impl Future<Output = Result<(), Error<Self::Error>>>
     where
         B: core::iter::ExactSizeIterator<Item = &'a [u8; BLOCK_SIZE]>;
+    #[cfg(feature = "async")]
+    fn lock_unlock(
+        &mut self,
+        _command: LockUnlock<'_>,
+    ) -> impl Future<Output = Result<(), Error<Self::Error>>> {
+        async { Err(ErrorKind::Unsupported.into()) }
+    }
+    #[cfg(feature = "async")]
+    fn program_csd(&mut self, _csd: CSD) -> impl Future<Output = Result<(), Error<Self::Error>>> {
+        async { Err(ErrorKind::Unsupported.into()) }
+    }
+    #[cfg(feature = "async")]
+    fn set_write_protect(
+        &mut self,
+        _address: u32,
+        _protect: bool,
+    ) -> impl Future<Output = Result<(), Error<Self::Error>>> {
+        async { Err(ErrorKind::Unsupported.into()) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_classify() {
+        use super::{spi, Classify, Error, ErrorKind};
+        use crate::sd::{response::R2, transfer::TokenError};
+
+        type BUSError = Error<spi::bus::Error<(), ()>>;
+        let error = BUSError::from(ErrorKind::NoResponse);
+        assert!(error.card_gone() && error.needs_reinit() && !error.retryable());
+        let error = BUSError::from(ErrorKind::NoResponse).with_status(Some(R2(0)));
+        assert!(!error.card_gone() && !error.needs_reinit() && error.retryable());
+        let error = BUSError::from(ErrorKind::CRC).with_status(Some(R2(0)));
+        assert!(error.retryable() && !error.media_error());
+        let error = BUSError::from(ErrorKind::CRC).with_status(None);
+        assert!(error.needs_reinit() && !error.retryable());
+        let error = BUSError::from(ErrorKind::WriteError).with_status(Some(R2(1 << 5)));
+        assert!(!error.media_error() && !error.retryable());
+        let error = BUSError::from(TokenError::CardECC);
+        assert!(error.media_error() && !error.retryable());
+        let error = BUSError::from(ErrorKind::Timeout).with_status(Some(R2(1)));
+        assert!(error.locked() && !error.retryable());
+        let error = BUSError::from(ErrorKind::BUS(spi::bus::Error::CS(())));
+        assert!(!error.retryable() && !error.card_gone());
+    }
 }
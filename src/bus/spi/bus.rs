@@ -11,12 +11,16 @@ use embedded_timers::{clock::Clock, instant::Instant};
 
 use crate::sd::{
     command::{AppCommand, Command},
-    response::{self, Response},
+    registers::SCR,
+    response::{self, Response, R2},
 };
 
-use crate::bus;
+use crate::bus::{self, Classify, ErrorKind, Phase};
+
+use super::Observer;
 
 #[derive(Debug, Display)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Error<SPI, CS> {
     #[display("spi error: {_0}")]
     SPI(SPI),
@@ -26,6 +30,29 @@ pub enum Error<SPI, CS> {
 
 impl<SPI: core::error::Error, CS: core::error::Error> core::error::Error for Error<SPI, CS> {}
 
+/// SPI transfer failure may be a glitch, while chip select failure won't go away
+impl<SPI, CS> bus::Classify for Error<SPI, CS> {
+    fn retryable(&self) -> bool {
+        matches!(self, Self::SPI(_))
+    }
+
+    fn needs_reinit(&self) -> bool {
+        false
+    }
+
+    fn card_gone(&self) -> bool {
+        false
+    }
+
+    fn media_error(&self) -> bool {
+        false
+    }
+
+    fn locked(&self) -> bool {
+        false
+    }
+}
+
 pub type BUSError<SPI, CS> = bus::Error<Error<SPI, CS>>;
 
 pub trait Transfer {
@@ -63,68 +90,130 @@ impl<E, T: SpiBus<u8, Error = E>> Transfer for T {
     }
 }
 
-pub struct Bus<SPI, CS, C> {
+/// Multiple block transfer to be stopped on failure
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum Transmission {
+    None,
+    Read,
+    Write,
+}
+
+pub struct Bus<SPI, CS, C: Clock, O = ()> {
     spi: SPI,
     cs: CS,
     pub(crate) clock: C,
+    /// Creation time, counting uptime from
+    epoch: C::Instant,
+    /// Read during init
+    pub(crate) scr: SCR,
+    pub(crate) observer: O,
+    activity: bus::Activity,
 }
 
-impl<E, SPI, CS, C, I> Bus<SPI, CS, C>
+impl<SPI, CS, C: Clock> Bus<SPI, CS, C> {
+    pub fn new(spi: SPI, cs: CS, clock: C) -> Self {
+        let (epoch, activity) = (clock.now(), bus::Activity::default());
+        Self { spi, cs, clock, epoch, scr: SCR::default(), observer: (), activity }
+    }
+
+    /// Reports protocol events to `observer`
+    pub fn with_observer<O: Observer>(self, observer: O) -> Bus<SPI, CS, C, O> {
+        let Self { spi, cs, clock, epoch, scr, activity, .. } = self;
+        Bus { spi, cs, clock, epoch, scr, observer, activity }
+    }
+}
+
+impl<E, SPI, CS, C, I, O> Bus<SPI, CS, C, O>
 where
     CS: OutputPin<Error = E>,
     C: Clock<Instant = I>,
+    O: Observer,
 {
-    pub fn new(spi: SPI, cs: CS, clock: C) -> Self {
-        Self { spi, cs, clock }
+    pub fn scr(&self) -> SCR {
+        self.scr
     }
 
     pub fn spi<R>(&mut self, f: impl Fn(&mut SPI) -> R) -> R {
         f(&mut self.spi)
     }
 
+    pub fn observer<R>(&mut self, f: impl FnOnce(&mut O) -> R) -> R {
+        f(&mut self.observer)
+    }
+
     pub(crate) fn select<T>(&mut self) -> Result<(), BUSError<T, E>> {
-        self.cs.set_low().map_err(|e| BUSError::BUS(Error::CS(e)))
+        self.observer.chip_select(true);
+        self.cs.set_low().map_err(|e| ErrorKind::BUS(Error::CS(e)).into())
     }
 
     pub(crate) fn deselect<T>(&mut self) -> Result<(), BUSError<T, E>> {
-        self.cs.set_high().map_err(|e| BUSError::BUS(Error::CS(e)))
+        self.observer.chip_select(false);
+        self.cs.set_high().map_err(|e| ErrorKind::BUS(Error::CS(e)).into())
     }
 }
 
 #[cfg_attr(not(feature = "async"), deasync::deasync)]
-impl<E, F, SPI, CS, C, I> Bus<SPI, CS, C>
+impl<E, F, SPI, CS, C, I, O> Bus<SPI, CS, C, O>
 where
     SPI: Transfer<Error = E>,
     CS: OutputPin<Error = F>,
     C: Clock<Instant = I>,
     I: Instant,
+    O: Observer,
 {
     pub(crate) async fn tx(&mut self, bytes: &[u8]) -> Result<(), BUSError<E, F>> {
-        self.spi.transfer(bytes, &mut []).await.map_err(|e| BUSError::BUS(Error::SPI(e)))
+        self.spi.transfer(bytes, &mut []).await.map_err(|e| ErrorKind::BUS(Error::SPI(e)).into())
     }
 
     pub(crate) async fn rx(&mut self, buffer: &mut [u8]) -> Result<(), BUSError<E, F>> {
-        self.spi.transfer(&[], buffer).await.map_err(|e| BUSError::BUS(Error::SPI(e)))
+        self.spi.transfer(&[], buffer).await.map_err(|e| ErrorKind::BUS(Error::SPI(e)).into())
     }
 
     pub(crate) async fn wait(&mut self, timeout: Duration) -> Result<(), BUSError<E, F>> {
-        let deadline = self.clock.now() + timeout;
+        let start = self.clock.now();
+        self.observer.busy_start();
+        let result = self.wait_until(start + timeout).await;
+        let elapsed = self.clock.now() - start;
+        self.activity.busy += elapsed;
+        self.observer.busy_end(elapsed);
+        result
+    }
+
+    async fn wait_until(&mut self, deadline: I) -> Result<(), BUSError<E, F>> {
         let mut byte = 0u8;
         while byte != 0xFFu8 {
             if self.clock.now() > deadline {
-                return Err(BUSError::Timeout);
+                return Err(BUSError::from(ErrorKind::Timeout).at(Phase::Busy));
             }
-            let buf = slice::from_mut(&mut byte);
-            self.spi.transfer(&[], buf).await.map_err(|e| BUSError::BUS(Error::SPI(e)))?;
+            self.rx(slice::from_mut(&mut byte)).await.map_err(|e| e.at(Phase::Busy))?;
         }
         Ok(())
     }
 
     pub(crate) async fn send_command(&mut self, cmd: Command) -> Result<Response, BUSError<E, F>> {
         let bytes: [u8; 6] = cmd.into();
-        trace!("Send CMD {:?} bytes {:X?}", cmd, &bytes);
-        self.tx(&bytes[..]).await?;
+        trace!("Send {:?}", cmd);
+        traced!(
+            tracing::trace_span!(
+                "command",
+                index = cmd.index(),
+                argument = cmd.argument(),
+                duration_us = tracing::field::Empty,
+                outcome = tracing::field::Empty
+            ),
+            self.exchange(cmd, &bytes)
+        )
+    }
+
+    async fn exchange(&mut self, cmd: Command, bytes: &[u8]) -> Result<Response, BUSError<E, F>> {
+        self.activity.commands += 1;
+        self.observer.command(cmd.index(), cmd.argument());
+        self.tx(bytes).await.map_err(|e| e.at(Phase::Command).during(cmd.index()))?;
+        self.read_response(cmd).await.map_err(|e| e.at(Phase::Response).during(cmd.index()))
+    }
 
+    async fn read_response(&mut self, cmd: Command) -> Result<Response, BUSError<E, F>> {
         if cmd == Command::StopTransmission {
             self.rx(&mut [0u8]).await?; // Skip stuff byte
         }
@@ -138,20 +227,21 @@ where
             }
         }
         if !r1.valid() {
-            return Err(BUSError::NoResponse);
+            return Err(ErrorKind::NoResponse.into());
         }
 
-        if let Some(e) = r1.error() {
-            return Err(BUSError::Command(e));
-        }
         let mut response = Response { r1, ..Default::default() };
-
         let size = cmd.expected_response_ex_size();
-        if size > 0 {
+        if size > 0 && r1.error().is_none() {
             let mut buffer = [0u8; 4];
             self.rx(&mut buffer[4 - size..]).await?;
             response.ex = u32::from_be_bytes(buffer);
         }
+        self.observer.response(cmd.index(), r1, response.ex);
+
+        if let Some(e) = r1.error() {
+            return Err(ErrorKind::Command(e).into());
+        }
         Ok(response)
     }
 
@@ -162,14 +252,51 @@ where
         self.send_command(Command::AppCommand(0)).await?;
         self.send_command(Command::App(cmd)).await
     }
+
+    async fn check_status(&mut self) -> Result<R2, BUSError<E, F>> {
+        self.deselect()?;
+        self.tx(&[0xFF; 2]).await?; // Release bytes to resynchronise
+        self.select()?;
+        let r = self.send_command(Command::SendStatus(0)).await?;
+        Ok(R2(r.ex as u8))
+    }
+
+    /// Brings card back to transfer state after `error`, leaving card selected
+    ///
+    /// Stops `transmission`, waits until not busy, clocks out release bytes
+    /// and then checks status with SEND_STATUS.
+    pub(crate) async fn recover(
+        &mut self,
+        error: BUSError<E, F>,
+        transmission: Transmission,
+    ) -> BUSError<E, F> {
+        // Nothing to resynchronise with if bus itself is broken
+        if matches!(error.kind(), ErrorKind::BUS(e) if !e.retryable()) {
+            return error;
+        }
+        trace!("Recover from failed {:?} transmission", transmission);
+        let stopped = match transmission {
+            Transmission::None => Ok(()),
+            Transmission::Read => self.send_command(Command::StopTransmission).await.map(|_| ()),
+            Transmission::Write => self.stop_write().await,
+        };
+        let idle = self.wait(Duration::from_millis(250)).await;
+        let status = match (stopped.and(idle).is_ok(), self.check_status().await) {
+            (true, Ok(status)) => Some(status),
+            _ => None,
+        };
+        trace!("Recovered with status {:?}", status);
+        error.with_status(status)
+    }
 }
 
-impl<E, F, SPI, CS, C, I> bus::Bus for Bus<SPI, CS, C>
+impl<E, F, SPI, CS, C, I, O> bus::Bus for Bus<SPI, CS, C, O>
 where
     SPI: Transfer<Error = E>,
     CS: OutputPin<Error = F>,
     C: Clock<Instant = I>,
     I: Instant,
+    O: Observer,
 {
     type Error = Error<E, F>;
 
@@ -180,4 +307,12 @@ where
     fn after(&mut self) -> Result<(), BUSError<E, F>> {
         self.deselect()
     }
+
+    fn uptime(&self) -> Option<Duration> {
+        Some(self.clock.now().duration_since(self.epoch))
+    }
+
+    fn take_activity(&mut self) -> bus::Activity {
+        core::mem::take(&mut self.activity)
+    }
 }
@@ -1,4 +1,5 @@
 pub mod bus;
+pub mod observer;
 pub mod read;
 pub mod write;
 
@@ -6,21 +7,26 @@ use embedded_hal::digital::OutputPin;
 use embedded_timers::{clock::Clock, instant::Instant};
 
 use crate::{
+    bus::{ErrorKind, Phase},
     delay::Delay,
     sd::{
         command::{AppCommand, Command, SendInterfaceCondition},
+        registers::SCR,
         response::{self, R1Status},
         Card,
     },
 };
+use bus::Transmission;
 pub use bus::{BUSError, Bus, Transfer};
+pub use observer::Observer;
 
-impl<E, F, SPI, CS, C, I> Bus<SPI, CS, C>
+impl<E, F, SPI, CS, C, I, O> Bus<SPI, CS, C, O>
 where
     SPI: Transfer<Error = E>,
     CS: OutputPin<Error = F>,
     C: Clock<Instant = I>,
     I: Instant,
+    O: Observer,
 {
     #[cfg_attr(not(feature = "async"), deasync::deasync)]
     async fn go_idle(&mut self, delay: &mut impl Delay) -> Result<(), BUSError<E, F>> {
@@ -29,14 +35,17 @@ where
             match self.send_command(Command::GoIdleState).await {
                 Ok(r) => match r.r1.has(R1Status::InIdleState) {
                     true => return Ok(()),
-                    false => return Err(BUSError::NotIdle),
+                    false => {
+                        let error = BUSError::from(ErrorKind::NotIdle);
+                        return Err(error.at(Phase::Response).during(0));
+                    }
                 },
-                Err(BUSError::NoResponse) => (),
+                Err(e) if matches!(e.kind(), ErrorKind::NoResponse) => (),
                 Err(e) => return Err(e),
             }
             delay.delay_ms(10).await;
         }
-        Err(BUSError::NoResponse)
+        Err(ErrorKind::NoResponse.into())
     }
 
     /// Before init, set SPI clock rate between 100KHZ and 400KHZ
@@ -48,32 +57,49 @@ where
         self.tx(&[0xFF; 10]).await?;
 
         self.select()?;
+        let result = self.identify(&mut delay).await;
+        self.deselect()?;
+        self.rx(&mut [0; 1]).await?; // Make MMC/SD release MISO
+        result
+    }
+
+    #[cfg_attr(not(feature = "async"), deasync::deasync)]
+    async fn identify(&mut self, delay: &mut impl Delay) -> Result<Card, BUSError<E, F>> {
         trace!("Go idle");
-        self.go_idle(&mut delay).await?;
+        self.go_idle(delay).await?;
 
         trace!("Query version");
-        let mut version = 1;
-        let r = self.send_command(Command::SendIfCond(SendInterfaceCondition::spi())).await?;
-        if !r.r1.has(R1Status::IllegalCommand) {
-            version = 2;
-            let r7 = response::R7(r.ex);
-            if !r7.voltage_accepted() || r7.echo_back_check_pattern() != 0xAA {
-                return Err(BUSError::Generic);
+        let cmd = Command::SendIfCond(SendInterfaceCondition::spi());
+        let version = match self.send_command(cmd).await {
+            Ok(r) => {
+                let r7 = response::R7(r.ex);
+                if !r7.voltage_accepted() || r7.echo_back_check_pattern() != 0xAA {
+                    let error = BUSError::from(ErrorKind::InterfaceCondition);
+                    return Err(error.at(Phase::Response).during(cmd.index()));
+                }
+                2
             }
-        }
+            Err(e) => match e.kind() {
+                // SD v1.x doesn't know SEND_IF_COND
+                ErrorKind::Command(r1) if r1.has(R1Status::IllegalCommand) => 1,
+                _ => return Err(e),
+            },
+        };
         trace!("Version is {}", version);
 
         trace!("Initialize");
+        let cmd = AppCommand::SDSendOpCond(version > 1);
         let mut r1 = response::R1::default();
         for _ in 0..100 {
-            r1 = self.send_app_command(AppCommand::SDSendOpCond(version > 1)).await?.r1;
+            r1 = self.send_app_command(cmd).await?.r1;
             if !r1.has(R1Status::InIdleState) {
                 break;
             }
             delay.delay_ms(10).await;
         }
         if r1.has(R1Status::InIdleState) {
-            return Err(BUSError::Generic);
+            let error = BUSError::from(ErrorKind::InitTimeout);
+            return Err(error.at(Phase::Response).during(cmd.index()));
         }
 
         trace!("Read OCR");
@@ -85,8 +111,78 @@ where
                 card = Card::SDHC;
             }
         }
-        self.deselect()?;
-        self.rx(&mut [0; 1]).await?; // Make MMC/SD release MISO
+
+        trace!("Read SCR");
+        self.scr = match self.read_scr().await {
+            Ok(scr) => scr,
+            // MMC has no SCR, go without optional commands it would tell about
+            Err(error) => {
+                trace!("SCR not available");
+                self.recover(error, Transmission::None).await;
+                SCR::default()
+            }
+        };
         Ok(card)
     }
+
+    #[cfg_attr(not(feature = "async"), deasync::deasync)]
+    async fn read_scr(&mut self) -> Result<SCR, BUSError<E, F>> {
+        let cmd = AppCommand::SendSCR;
+        self.send_app_command(cmd).await?;
+        let mut buffer = [0u8; 8];
+        self.read_block(&mut buffer).await.map_err(|e| e.during(cmd.index()))?;
+        Ok(SCR::new(u64::from_be_bytes(buffer)))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use core::convert::Infallible;
+
+    use embedded_timers::instant::TimespecInstant;
+
+    /// Reads all zeros, which is R1 without any flag
+    pub struct SPI;
+
+    impl super::Transfer for SPI {
+        type Error = ();
+
+        #[cfg(not(feature = "async"))]
+        fn transfer(&mut self, _: &[u8], rx: &mut [u8]) -> Result<(), ()> {
+            rx.fill(0);
+            Ok(())
+        }
+
+        #[cfg(feature = "async")]
+        async fn transfer(&mut self, _: &[u8], rx: &mut [u8]) -> Result<(), ()> {
+            rx.fill(0);
+            Ok(())
+        }
+    }
+
+    pub struct CS;
+
+    impl embedded_hal::digital::ErrorType for CS {
+        type Error = Infallible;
+    }
+
+    impl embedded_hal::digital::OutputPin for CS {
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            Ok(())
+        }
+    }
+
+    pub struct Clock;
+
+    impl embedded_timers::clock::Clock for Clock {
+        type Instant = TimespecInstant;
+
+        fn now(&self) -> TimespecInstant {
+            TimespecInstant::new(0, 0)
+        }
+    }
 }
@@ -0,0 +1,90 @@
+use core::time::Duration;
+
+use crate::sd::response::R1;
+
+/// Protocol events of [`Bus`](super::Bus), e.g. for protocol analysers, metrics
+/// or asserting command order in tests
+///
+/// Every event is ignored by default, so that `()` as observer costs nothing.
+pub trait Observer {
+    /// Command `index` with `argument` sent
+    fn command(&mut self, _index: u8, _argument: u32) {}
+
+    /// Response to command `index` received, `ex` holding extension bytes of R2, R3 or R7,
+    /// reported before R1 error flags are checked
+    fn response(&mut self, _index: u8, _r1: R1, _ex: u32) {}
+
+    /// Data token sent or received, including data response and data error tokens
+    fn data_token(&mut self, _token: u8) {}
+
+    fn busy_start(&mut self) {}
+
+    /// Busy wait ended, timed out or failed after `elapsed`
+    fn busy_end(&mut self, _elapsed: Duration) {}
+
+    /// Chip select asserted if `selected`, deasserted otherwise
+    fn chip_select(&mut self, _selected: bool) {}
+}
+
+impl Observer for () {}
+
+#[cfg(test)]
+mod test {
+    use alloc::vec::Vec;
+
+    use super::Observer;
+    use crate::sd::response::R1;
+
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        Command(u8, u32),
+        Response(u8, u8, u32),
+        ChipSelect(bool),
+    }
+
+    #[derive(Default)]
+    struct Recorder(Vec<Event>);
+
+    impl Observer for Recorder {
+        fn command(&mut self, index: u8, argument: u32) {
+            self.0.push(Event::Command(index, argument));
+        }
+
+        fn response(&mut self, index: u8, r1: R1, ex: u32) {
+            self.0.push(Event::Response(index, r1.0, ex));
+        }
+
+        fn chip_select(&mut self, selected: bool) {
+            self.0.push(Event::ChipSelect(selected));
+        }
+    }
+
+    #[test]
+    fn test_observer() {
+        use super::super::{
+            test::{Clock, CS, SPI},
+            Bus,
+        };
+        use crate::{block::test::block_on, sd::command::Command};
+
+        let mut bus = Bus::new(SPI, CS, Clock).with_observer(Recorder::default());
+        bus.select::<()>().unwrap();
+        block_on(bus.send_command(Command::SendStatus(0))).unwrap();
+        block_on(bus.send_command(Command::ReadSingleBlock(8))).unwrap();
+        bus.deselect::<()>().unwrap();
+        let mut events = Vec::new();
+        let events = bus.observer(move |recorder| {
+            events.append(&mut recorder.0);
+            events
+        });
+        let expected = [
+            Event::ChipSelect(true),
+            Event::Command(13, 0),
+            Event::Response(13, 0, 0),
+            Event::Command(17, 8),
+            Event::Response(17, 0, 0),
+            Event::ChipSelect(false),
+        ];
+        assert_eq!(events, expected);
+    }
+}
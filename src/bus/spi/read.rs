@@ -5,70 +5,165 @@ use embedded_hal::digital::OutputPin;
 use embedded_timers::{clock::Clock, instant::Instant};
 
 use crate::{
-    bus::Read,
+    bus::{ErrorKind, Phase, Read},
     sd::{
-        command::Command,
-        registers::CSD,
+        command::{AppCommand, Command},
+        registers::{SDStatus, CSD},
+        response::R2,
         transfer::{Token, TokenError},
         BLOCK_SIZE,
     },
 };
 
-use super::bus::{BUSError, Bus, Error, Transfer};
+use super::{
+    bus::{BUSError, Bus, Error, Transfer, Transmission},
+    Observer,
+};
 
-impl<E, F, SPI, CS, C, I> Bus<SPI, CS, C>
+impl<E, F, SPI, CS, C, I, O> Bus<SPI, CS, C, O>
 where
     SPI: Transfer<Error = E>,
     CS: OutputPin<Error = F>,
     C: Clock<Instant = I>,
     I: Instant,
+    O: Observer,
 {
     #[cfg_attr(not(feature = "async"), deasync::deasync)]
-    pub(crate) async fn read_block(&mut self, block: &mut [u8]) -> Result<(), BUSError<E, F>> {
+    async fn read_token(&mut self) -> Result<Token, BUSError<E, F>> {
         let deadline = self.clock.now() + Duration::from_millis(100);
-        let token = loop {
+        loop {
             if self.clock.now() > deadline {
-                return Err(BUSError::Timeout);
+                return Err(ErrorKind::Timeout.into());
             }
             let mut byte = 0u8;
             self.rx(slice::from_mut(&mut byte)).await?;
             if byte == 0xFF {
                 continue;
             }
-            match Token::try_from(byte) {
-                Ok(token) => break token,
+            let token = match Token::try_from(byte) {
                 Err(TokenError::NotToken) => continue,
-                Err(e) => return Err(BUSError::Transfer(e)),
-            }
-        };
+                token => token,
+            };
+            self.observer.data_token(byte);
+            return token.map_err(|e| ErrorKind::Transfer(e).into());
+        }
+    }
+
+    #[cfg_attr(not(feature = "async"), deasync::deasync)]
+    pub(crate) async fn read_block(&mut self, block: &mut [u8]) -> Result<(), BUSError<E, F>> {
+        let token = self.read_token().await.map_err(|e| e.at(Phase::DataToken))?;
         if token != Token::Start {
-            return Err(BUSError::Generic);
+            let error = BUSError::from(ErrorKind::UnexpectedToken(token as u8));
+            return Err(error.at(Phase::DataToken));
         }
-        self.rx(block).await?;
+        self.rx(block).await.map_err(|e| e.at(Phase::Data))?;
         let mut crc = [0u8; 2];
-        self.rx(&mut crc).await
+        self.rx(&mut crc).await.map_err(|e| e.at(Phase::CRC))
+    }
+
+    #[cfg_attr(not(feature = "async"), deasync::deasync)]
+    async fn read_data(&mut self, cmd: Command, buffer: &mut [u8]) -> Result<(), BUSError<E, F>> {
+        match cmd {
+            Command::App(cmd) => self.send_app_command(cmd).await?,
+            _ => self.send_command(cmd).await?,
+        };
+        self.read_block(buffer).await.map_err(|e| e.during(cmd.index()))
+    }
+
+    /// Reads register or status data block responded to `cmd`
+    #[cfg_attr(not(feature = "async"), deasync::deasync)]
+    async fn read_register(
+        &mut self,
+        cmd: Command,
+        buffer: &mut [u8],
+    ) -> Result<(), BUSError<E, F>> {
+        self.tx(&[0xFF; 5]).await?;
+        self.select()?;
+        let mut result = self.read_data(cmd, buffer).await;
+        if let Err(error) = result {
+            result = Err(self.recover(error, Transmission::None).await);
+        }
+        self.deselect()?;
+        self.tx(&[0xFF]).await?; // Extra byte to release MISO
+        result
+    }
+
+    #[cfg_attr(not(feature = "async"), deasync::deasync)]
+    async fn start_read(
+        &mut self,
+        cmd: Command,
+        num_blocks: usize,
+        predefined: bool,
+    ) -> Result<(), BUSError<E, F>> {
+        if predefined {
+            self.send_command(Command::SetBlockCount(num_blocks as u32)).await?;
+        }
+        self.send_command(cmd).await?;
+        Ok(())
+    }
+
+    /// Reads data blocks of started transfer, counting those completed
+    #[cfg_attr(not(feature = "async"), deasync::deasync)]
+    async fn read_blocks<'a, B>(
+        &mut self,
+        blocks: B,
+        stop: bool,
+        completed: &mut u32,
+    ) -> Result<(), BUSError<E, F>>
+    where
+        B: core::iter::Iterator<Item = &'a mut [u8; BLOCK_SIZE]>,
+    {
+        for block in blocks {
+            self.read_block(block).await?;
+            *completed += 1;
+        }
+        if stop {
+            self.send_command(Command::StopTransmission).await?;
+            self.wait(Duration::from_millis(100)).await?;
+        }
+        Ok(())
     }
 }
 
 #[cfg_attr(not(feature = "async"), deasync::deasync)]
-impl<E, F, SPI, CS, C, I> Read for Bus<SPI, CS, C>
+impl<E, F, SPI, CS, C, I, O> Read for Bus<SPI, CS, C, O>
 where
     SPI: Transfer<Error = E>,
     CS: OutputPin<Error = F>,
     C: Clock<Instant = I>,
     I: Instant,
+    O: Observer,
 {
     type Error = Error<E, F>;
 
     async fn read_csd(&mut self) -> Result<CSD, BUSError<E, F>> {
+        let mut buffer = [0u8; 16];
+        self.read_register(Command::SendCSD(0), &mut buffer).await?;
+        CSD::try_from(u128::from_be_bytes(buffer)).ok_or(ErrorKind::InvalidCSD.into())
+    }
+
+    async fn read_sd_status(&mut self) -> Result<SDStatus, BUSError<E, F>> {
+        let mut buffer = [0u8; 64];
+        self.read_register(Command::App(AppCommand::SDStatus), &mut buffer).await?;
+        Ok(SDStatus(buffer))
+    }
+
+    async fn read_status(&mut self) -> Result<R2, BUSError<E, F>> {
         self.tx(&[0xFF; 5]).await?;
         self.select()?;
-        self.send_command(Command::SendCSD(0)).await?;
-        let mut buffer = [0u8; 16];
-        self.read_block(&mut buffer).await?;
+        let mut result = self.send_command(Command::SendStatus(0)).await;
+        if let Err(error) = result {
+            result = Err(self.recover(error, Transmission::None).await);
+        }
         self.deselect()?;
         self.tx(&[0xFF]).await?; // Extra byte to release MISO
-        CSD::try_from(u128::from_be_bytes(buffer)).ok_or(BUSError::Generic)
+        Ok(R2(result?.ex as u8))
+    }
+
+    async fn read_write_protect(&mut self, address: u32) -> Result<u32, BUSError<E, F>> {
+        let mut buffer = [0u8; 4];
+        self.read_register(Command::SendWriteProtect(address), &mut buffer).await?;
+        Ok(u32::from_be_bytes(buffer))
     }
 
     async fn read<'a, B>(&mut self, address: u32, blocks: B) -> Result<(), BUSError<E, F>>
@@ -78,19 +173,27 @@ where
         self.tx(&[0xFF; 5]).await?;
         self.select()?;
         let num_blocks = blocks.len();
+        // Pre-defined transfer ends by itself without STOP_TRANSMISSION
+        let predefined = num_blocks > 1 && self.scr.set_block_count_supported();
         let cmd = match num_blocks {
             1 => Command::ReadSingleBlock(address),
             _ => Command::ReadMultipleBlock(address),
         };
-        self.send_command(cmd).await?;
-        for block in blocks {
-            self.read_block(block).await?;
+        let (mut completed, mut transmission) = (0, Transmission::None);
+        let mut result = self.start_read(cmd, num_blocks, predefined).await;
+        if result.is_ok() {
+            if num_blocks > 1 {
+                transmission = Transmission::Read;
+            }
+            let stop = num_blocks > 1 && !predefined;
+            result = self.read_blocks(blocks, stop, &mut completed).await;
+            result = result.map_err(|e| e.during(cmd.index()));
         }
-        if num_blocks > 1 {
-            self.send_command(Command::StopTransmission).await?;
-            self.wait(Duration::from_millis(100)).await?;
+        if let Err(error) = result {
+            result = Err(self.recover(error, transmission).await);
         }
         self.deselect()?;
-        self.tx(&[0xFF]).await // Extra byte to release MISO
+        self.tx(&[0xFF]).await?; // Extra byte to release MISO
+        result.map_err(|e| e.with_completed(Some(completed)))
     }
 }
@@ -5,23 +5,158 @@ use embedded_hal::digital::OutputPin;
 use embedded_timers::{clock::Clock, instant::Instant};
 
 use crate::{
-    bus::Write,
+    bus::{Classify, ErrorKind, Phase, Write},
     sd::{
-        command::Command,
-        transfer::{Response, Token, TokenError},
+        command::{crc7, AppCommand, Command, LockUnlock, MAX_PASSWORD_LENGTH},
+        registers::CSD,
+        response::R2,
+        transfer::{Response, Token},
         BLOCK_SIZE,
     },
 };
 
-use super::bus::{BUSError, Bus, Error, Transfer};
+use super::{
+    bus::{BUSError, Bus, Error, Transfer, Transmission},
+    Observer,
+};
+
+/// Force erase may take up to 3 minutes
+const FORCE_ERASE_TIMEOUT: Duration = Duration::from_secs(180);
+
+impl<E, F, SPI, CS, C, I, O> Bus<SPI, CS, C, O>
+where
+    SPI: Transfer<Error = E>,
+    CS: OutputPin<Error = F>,
+    C: Clock<Instant = I>,
+    I: Instant,
+    O: Observer,
+{
+    #[cfg_attr(not(feature = "async"), deasync::deasync)]
+    pub(crate) async fn write_block(
+        &mut self,
+        token: Token,
+        block: &[u8],
+        timeout: Duration,
+    ) -> Result<(), BUSError<E, F>> {
+        self.observer.data_token(token as u8);
+        self.tx(&[token as u8]).await.map_err(|e| e.at(Phase::DataToken))?;
+        self.tx(block).await.map_err(|e| e.at(Phase::Data))?;
+        let crc = [0u8; 2];
+        self.tx(&crc).await.map_err(|e| e.at(Phase::CRC))?;
+        let mut byte = 0u8;
+        self.rx(slice::from_mut(&mut byte)).await.map_err(|e| e.at(Phase::DataToken))?;
+        self.observer.data_token(byte);
+        let (kind, phase) = match Response::try_from(byte) {
+            Some(Response::Accepted) => return self.wait(timeout).await,
+            Some(Response::CRCError) => (ErrorKind::CRC, Phase::CRC),
+            Some(Response::WriteError) => (ErrorKind::WriteError, Phase::Data),
+            None => (ErrorKind::InvalidDataResponse(byte), Phase::DataToken),
+        };
+        Err(BUSError::from(kind).at(phase))
+    }
+
+    /// Stop token ending multiple block write
+    #[cfg_attr(not(feature = "async"), deasync::deasync)]
+    pub(crate) async fn stop_write(&mut self) -> Result<(), BUSError<E, F>> {
+        self.observer.data_token(Token::Stop as u8);
+        self.tx(&[Token::Stop as u8, 0xFF]).await
+    }
+
+    #[cfg_attr(not(feature = "async"), deasync::deasync)]
+    async fn send_data(&mut self, cmd: Command, data: &[u8]) -> Result<(), BUSError<E, F>> {
+        self.send_command(cmd).await?;
+        let result = match data.is_empty() {
+            true => self.wait(Duration::from_millis(250)).await,
+            false => self.write_block(Token::Start, data, Duration::from_millis(250)).await,
+        };
+        result.map_err(|e| e.during(cmd.index()))
+    }
+
+    /// Sends command with R1b response followed by data block, then waits until not busy
+    #[cfg_attr(not(feature = "async"), deasync::deasync)]
+    async fn send_busy(&mut self, cmd: Command, data: &[u8]) -> Result<(), BUSError<E, F>> {
+        self.tx(&[0xFF; 5]).await?;
+        self.select()?;
+        let mut result = self.send_data(cmd, data).await;
+        if let Err(error) = result {
+            result = Err(self.recover(error, Transmission::None).await);
+        }
+        self.deselect()?;
+        self.tx(&[0xFF]).await?; // Extra byte to release MISO
+        result
+    }
+
+    #[cfg_attr(not(feature = "async"), deasync::deasync)]
+    async fn start_write(
+        &mut self,
+        cmd: Command,
+        num_blocks: usize,
+        predefined: bool,
+    ) -> Result<(), BUSError<E, F>> {
+        if num_blocks > 1 {
+            // Lets card pre-erase, reset after each multiple block write
+            let count = AppCommand::SetWriteBlockEraseCount(num_blocks as u32);
+            self.send_app_command(count).await?;
+        }
+        if predefined {
+            self.send_command(Command::SetBlockCount(num_blocks as u32)).await?;
+        }
+        self.send_command(cmd).await?;
+        Ok(())
+    }
+
+    /// Writes data blocks of started transfer, then Stop token if `stop`
+    #[cfg_attr(not(feature = "async"), deasync::deasync)]
+    async fn write_blocks<'a, B>(
+        &mut self,
+        token: Token,
+        blocks: B,
+        stop: bool,
+    ) -> Result<(), BUSError<E, F>>
+    where
+        B: core::iter::Iterator<Item = &'a [u8; BLOCK_SIZE]>,
+    {
+        for block in blocks {
+            self.write_block(token, block, Duration::from_millis(250)).await?;
+        }
+        if stop {
+            self.stop_write().await?;
+            self.wait(Duration::from_millis(250)).await?;
+        }
+        Ok(())
+    }
+
+    /// Number of blocks well written by last write, with SEND_NUM_WR_BLOCKS
+    #[cfg_attr(not(feature = "async"), deasync::deasync)]
+    async fn num_write_blocks(&mut self) -> Result<u32, BUSError<E, F>> {
+        let cmd = AppCommand::SendNumWriteBlocks;
+        self.send_app_command(cmd).await?;
+        let mut buffer = [0u8; 4];
+        self.read_block(&mut buffer).await.map_err(|e| e.during(cmd.index()))?;
+        Ok(u32::from_be_bytes(buffer))
+    }
+
+    #[cfg_attr(not(feature = "async"), deasync::deasync)]
+    async fn send_lock_unlock(&mut self, data: &[u8]) -> Result<(), BUSError<E, F>> {
+        self.send_command(Command::SetBlockLength(data.len() as u32)).await?;
+        self.send_command(Command::LockUnlock).await?;
+        let timeout = match data.len() {
+            1 => FORCE_ERASE_TIMEOUT,
+            _ => Duration::from_millis(250),
+        };
+        let result = self.write_block(Token::Start, data, timeout).await;
+        result.map_err(|e| e.during(Command::LockUnlock.index()))
+    }
+}
 
 #[cfg_attr(not(feature = "async"), deasync::deasync)]
-impl<E, F, SPI, CS, C, I> Write for Bus<SPI, CS, C>
+impl<E, F, SPI, CS, C, I, O> Write for Bus<SPI, CS, C, O>
 where
     SPI: Transfer<Error = E>,
     CS: OutputPin<Error = F>,
     C: Clock<Instant = I>,
     I: Instant,
+    O: Observer,
 {
     type Error = Error<E, F>;
 
@@ -32,31 +167,94 @@ where
         self.tx(&[0xFF; 5]).await?;
         self.select()?;
         let num_blocks = blocks.len();
-        let (cmd, token) = match num_blocks {
-            1 => (Command::WriteBlock(address), Token::Start),
-            _ => (Command::WriteMultipleBlock(address), Token::StartWriteMultipleBlock),
-        };
-        self.send_command(cmd).await?;
-        for block in blocks {
-            self.tx(&[token as u8]).await?;
-            self.tx(block).await?;
-            let crc = [0u8; 2];
-            self.tx(&crc).await?;
-            let mut byte = 0u8;
-            self.rx(slice::from_mut(&mut byte)).await?;
-            match Response::try_from(byte) {
-                Some(Response::Accepted) => (),
-                Some(_) => return Err(BUSError::Transfer(TokenError::Generic)),
-                None => return Err(BUSError::Generic),
+        // Pre-defined transfer ends by itself without Stop token
+        let predefined = num_blocks > 1 && self.scr.set_block_count_supported();
+        let (cmd, token, transmission) = match num_blocks {
+            1 => (Command::WriteBlock(address), Token::Start, Transmission::None),
+            _ => {
+                let cmd = Command::WriteMultipleBlock(address);
+                (cmd, Token::StartWriteMultipleBlock, Transmission::Write)
             }
-            self.wait(Duration::from_millis(250)).await?;
+        };
+        if let Err(error) = self.start_write(cmd, num_blocks, predefined).await {
+            let error = self.recover(error, Transmission::None).await;
+            self.deselect()?;
+            self.tx(&[0xFF]).await?; // Extra byte to release MISO
+            return Err(error.with_completed(Some(0)));
         }
-        if num_blocks > 1 {
-            self.tx(&[Token::Stop as u8, 0xFF]).await?;
-            self.wait(Duration::from_millis(250)).await?;
+        let mut result = self.write_blocks(token, blocks, num_blocks > 1 && !predefined).await;
+        result = result.map_err(|e| e.during(cmd.index()));
+        if let Err(error) = result {
+            let error = self.recover(error, transmission).await;
+            // Blocks accepted may still fail programming, ask card for those well written
+            let completed = match error.needs_reinit() {
+                true => None,
+                false => self.num_write_blocks().await.ok(),
+            };
+            result = Err(error.with_completed(completed));
         }
         self.deselect()?;
         self.tx(&[0xFF]).await?; // Extra byte to release MISO
-        Ok(())
+        result
+    }
+
+    async fn lock_unlock(&mut self, command: LockUnlock<'_>) -> Result<(), BUSError<E, F>> {
+        let mut data = [0u8; 2 + 2 * MAX_PASSWORD_LENGTH];
+        let length = command.encode(&mut data).ok_or(ErrorKind::PasswordTooLong)?;
+        self.tx(&[0xFF; 5]).await?;
+        self.select()?;
+        let mut result = self.send_lock_unlock(&data[..length]).await;
+        if let Err(error) = result {
+            result = Err(self.recover(error, Transmission::None).await);
+        }
+        // Block length only affects LOCK_UNLOCK on SDHC, but matters for SDSC
+        let restored = self.send_command(Command::SetBlockLength(BLOCK_SIZE as u32)).await;
+        let status = self.send_command(Command::SendStatus(0)).await;
+        self.deselect()?;
+        self.tx(&[0xFF]).await?; // Extra byte to release MISO
+        result?;
+        restored?;
+        match R2(status?.ex as u8).lock_unlock_failed() {
+            true => Err(ErrorKind::LockUnlockFailed.into()),
+            false => Ok(()),
+        }
+    }
+
+    async fn program_csd(&mut self, csd: CSD) -> Result<(), BUSError<E, F>> {
+        let mut data = csd.raw().to_be_bytes();
+        data[15] = crc7(&data[..15]);
+        self.send_busy(Command::ProgramCSD, &data).await
+    }
+
+    async fn set_write_protect(
+        &mut self,
+        address: u32,
+        protect: bool,
+    ) -> Result<(), BUSError<E, F>> {
+        let cmd = match protect {
+            true => Command::SetWriteProtect(address),
+            false => Command::ClearWriteProtect(address),
+        };
+        self.send_busy(cmd, &[]).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_password_too_long() {
+        use super::super::{
+            test::{Clock, CS, SPI},
+            Bus,
+        };
+        use crate::{
+            block::test::block_on,
+            bus::{ErrorKind, Write},
+            sd::command::LockUnlock,
+        };
+
+        let mut bus = Bus::new(SPI, CS, Clock);
+        let error = block_on(bus.lock_unlock(LockUnlock::Unlock(&[0; 17]))).unwrap_err();
+        assert!(matches!(error.kind(), ErrorKind::PasswordTooLong));
     }
 }
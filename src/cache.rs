@@ -0,0 +1,353 @@
+use core::iter;
+
+use crate::{
+    block::{Device, Error},
+    sd::BLOCK_SIZE,
+};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Eviction {
+    /// Least recently used
+    LRU,
+    /// Second chance, cheaper than LRU
+    Clock,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Mode {
+    WriteThrough,
+    /// Modified blocks reach the device on eviction or `flush`
+    WriteBack,
+}
+
+pub struct Slot {
+    address: u32,
+    data: [u8; BLOCK_SIZE],
+    valid: bool,
+    dirty: bool,
+    referenced: bool,
+    stamp: u32,
+}
+
+impl Slot {
+    pub const fn new() -> Self {
+        let data = [0; BLOCK_SIZE];
+        Self { address: 0, data, valid: false, dirty: false, referenced: false, stamp: 0 }
+    }
+}
+
+impl Default for Slot {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn find(slots: &[Slot], address: u32) -> Option<usize> {
+    slots.iter().position(|slot| slot.valid && slot.address == address)
+}
+
+/// Block cache in caller provided slots
+///
+/// Transfers of several consecutive uncached blocks go directly to the device,
+/// so that bulk transfers won't evict frequently used blocks.
+/// In write-back mode `flush` before dropping the cache.
+pub struct Cache<'a, D> {
+    device: D,
+    slots: &'a mut [Slot],
+    eviction: Eviction,
+    mode: Mode,
+    tick: u32,
+    hand: usize,
+}
+
+impl<'a, D: Device> Cache<'a, D> {
+    /// Panics if `slots` is empty
+    pub fn new(device: D, slots: &'a mut [Slot], eviction: Eviction, mode: Mode) -> Self {
+        assert!(!slots.is_empty());
+        slots.iter_mut().for_each(|slot| slot.valid = false);
+        Self { device, slots, eviction, mode, tick: 0, hand: 0 }
+    }
+
+    /// Discards cached blocks without writing back
+    pub fn invalidate(&mut self) {
+        self.slots.iter_mut().for_each(|slot| slot.valid = false);
+    }
+
+    /// Without writing back, `flush` first in write-back mode
+    pub fn into_inner(self) -> D {
+        self.device
+    }
+
+    fn touch(&mut self, index: usize) {
+        self.tick = self.tick.wrapping_add(1);
+        let slot = &mut self.slots[index];
+        slot.stamp = self.tick;
+        slot.referenced = true;
+    }
+
+    fn victim(&mut self) -> usize {
+        if let Some(index) = self.slots.iter().position(|slot| !slot.valid) {
+            return index;
+        }
+        match self.eviction {
+            Eviction::LRU => {
+                let stamp = |index: &usize| self.tick.wrapping_sub(self.slots[*index].stamp);
+                (0..self.slots.len()).max_by_key(stamp).unwrap_or_default()
+            }
+            Eviction::Clock => loop {
+                let index = self.hand;
+                self.hand = (self.hand + 1) % self.slots.len();
+                let slot = &mut self.slots[index];
+                if !slot.referenced {
+                    return index;
+                }
+                slot.referenced = false;
+            },
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "async"), deasync::deasync)]
+impl<'a, D: Device> Cache<'a, D> {
+    /// Picks a slot for `address`, writing back evicted block if modified
+    async fn allocate(&mut self, address: u32) -> Result<usize, D::Error> {
+        let index = self.victim();
+        let slot = &mut self.slots[index];
+        if slot.valid && slot.dirty {
+            self.device.write(slot.address, iter::once(&slot.data)).await?;
+        }
+        *slot = Slot { address, valid: true, dirty: false, ..*slot };
+        self.touch(index);
+        Ok(index)
+    }
+
+    async fn fill(&mut self, address: u32) -> Result<usize, D::Error> {
+        let index = self.allocate(address).await?;
+        let slot = &mut self.slots[index];
+        slot.valid = false;
+        self.device.read(address, iter::once(&mut slot.data)).await?;
+        slot.valid = true;
+        Ok(index)
+    }
+
+    /// Writes back modified blocks, merging adjacent ones into single transfer
+    pub async fn flush(&mut self) -> Result<(), D::Error> {
+        let dirty = |slot: &&Slot| slot.valid && slot.dirty;
+        while let Some(start) = self.slots.iter().filter(dirty).map(|slot| slot.address).min() {
+            let slots = &*self.slots;
+            let is_dirty = |address| find(slots, address).is_some_and(|index| slots[index].dirty);
+            let run = (start..=u32::MAX).take_while(|&address| is_dirty(address)).count() as u32;
+            let blocks =
+                (start..start + run).map(|address| &slots[find(slots, address).unwrap()].data);
+            self.device.write(start, blocks).await?;
+            for address in start..start + run {
+                let index = find(self.slots, address).unwrap();
+                self.slots[index].dirty = false;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg_attr(not(feature = "async"), deasync::deasync)]
+impl<'a, D: Device> Device for Cache<'a, D> {
+    type Error = Error<D::Error>;
+
+    fn num_blocks(&self) -> u32 {
+        self.device.num_blocks()
+    }
+
+    async fn read<'b, B>(&mut self, address: u32, blocks: B) -> Result<(), Self::Error>
+    where
+        B: core::iter::ExactSizeIterator<Item = &'b mut [u8; BLOCK_SIZE]>,
+    {
+        let mut blocks = blocks;
+        let end = address.checked_add(blocks.len() as u32).ok_or(Error::OutOfRange)?;
+        let mut address = address;
+        while address < end {
+            let misses = (address..end).take_while(|&a| find(self.slots, a).is_none()).count();
+            if misses > 1 {
+                self.device.read(address, blocks.by_ref().take(misses)).await?;
+                address += misses as u32;
+                continue;
+            }
+            let index = match find(self.slots, address) {
+                Some(index) => index,
+                None => self.fill(address).await?,
+            };
+            self.touch(index);
+            if let Some(block) = blocks.next() {
+                block.copy_from_slice(&self.slots[index].data);
+            }
+            address += 1;
+        }
+        Ok(())
+    }
+
+    async fn write<'b, B>(&mut self, address: u32, blocks: B) -> Result<(), Self::Error>
+    where
+        B: core::iter::ExactSizeIterator<Item = &'b [u8; BLOCK_SIZE]>,
+    {
+        let mut blocks = blocks;
+        let end = address.checked_add(blocks.len() as u32).ok_or(Error::OutOfRange)?;
+        let mut address = address;
+        while address < end {
+            let cached = find(self.slots, address).is_some();
+            let run = (address..end).take_while(|&a| find(self.slots, a).is_some() == cached);
+            let run = run.count() as u32;
+            if !cached && (run > 1 || self.mode == Mode::WriteThrough) {
+                self.device.write(address, blocks.by_ref().take(run as usize)).await?;
+                address += run;
+                continue;
+            }
+            let run = if cached { run } else { 1 };
+            for address in address..address + run {
+                let index = match find(self.slots, address) {
+                    Some(index) => index,
+                    None => self.allocate(address).await?,
+                };
+                self.touch(index);
+                let slot = &mut self.slots[index];
+                if let Some(block) = blocks.next() {
+                    slot.data.copy_from_slice(block);
+                }
+                slot.dirty = self.mode == Mode::WriteBack;
+            }
+            if self.mode == Mode::WriteThrough {
+                let slots = &*self.slots;
+                let blocks = (address..address + run).map(|a| &slots[find(slots, a).unwrap()].data);
+                if let Err(e) = self.device.write(address, blocks).await {
+                    // Device content unknown, don't serve data it may never have got
+                    for address in address..address + run {
+                        let index = find(self.slots, address).unwrap();
+                        self.slots[index].valid = false;
+                    }
+                    return Err(e.into());
+                }
+            }
+            address += run;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_write_back_merge() {
+        use super::{Cache, Eviction, Mode, Slot};
+        use crate::block::{
+            test::{block_on, Memory},
+            Device,
+        };
+
+        let mut memory = Memory::<8>::default();
+        let mut slots = [const { Slot::new() }; 4];
+        let mut cache = Cache::new(&mut memory, &mut slots, Eviction::LRU, Mode::WriteBack);
+        for address in [3, 1, 2, 6] {
+            let block = [address as u8; 512];
+            block_on(cache.write(address, core::iter::once(&block))).unwrap();
+        }
+        let mut block = [0u8; 512];
+        block_on(cache.read(2, core::iter::once(&mut block))).unwrap();
+        assert_eq!(block, [2u8; 512]);
+        block_on(cache.flush()).unwrap();
+        let memory = cache.into_inner();
+        assert_eq!((memory.reads, memory.writes), (0, 2));
+        for address in [1, 2, 3, 6] {
+            assert_eq!(memory.blocks[address], [address as u8; 512]);
+        }
+    }
+
+    #[test]
+    fn test_eviction() {
+        use super::{Cache, Eviction, Mode, Slot};
+        use crate::block::{
+            test::{block_on, Memory},
+            Device,
+        };
+
+        let mut memory = Memory::<8>::default();
+        let mut slots = [const { Slot::new() }; 2];
+        let mut cache = Cache::new(&mut memory, &mut slots, Eviction::LRU, Mode::WriteBack);
+        let mut block = [0u8; 512];
+        for address in [0, 1, 0, 2, 0] {
+            block_on(cache.read(address, core::iter::once(&mut block))).unwrap();
+        }
+        let memory = cache.into_inner();
+        assert_eq!(memory.reads, 3);
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        use super::{Cache, Eviction, Mode, Slot};
+        use crate::block::{
+            test::{block_on, Memory},
+            Device, Error,
+        };
+
+        let mut slots = [const { Slot::new() }; 2];
+        let mut cache =
+            Cache::new(Memory::<8>::default(), &mut slots, Eviction::LRU, Mode::WriteBack);
+        let mut blocks = [[0u8; 512]; 2];
+        let result = block_on(cache.read(u32::MAX, blocks.iter_mut()));
+        assert!(matches!(result, Err(Error::OutOfRange)));
+        let result = block_on(cache.write(u32::MAX, blocks.iter()));
+        assert!(matches!(result, Err(Error::OutOfRange)));
+    }
+
+    #[test]
+    fn test_clock_eviction() {
+        use super::{Cache, Eviction, Mode, Slot};
+        use crate::block::{
+            test::{block_on, Memory},
+            Device,
+        };
+
+        // Second chance evicts block 1 hit just before, which LRU keeps
+        for (eviction, reads) in [(Eviction::Clock, 5), (Eviction::LRU, 4)] {
+            let mut memory = Memory::<8>::default();
+            let mut slots = [const { Slot::new() }; 2];
+            let mut cache = Cache::new(&mut memory, &mut slots, eviction, Mode::WriteBack);
+            let mut block = [0u8; 512];
+            for address in [0, 1, 2, 1, 0, 1] {
+                block_on(cache.read(address, core::iter::once(&mut block))).unwrap();
+            }
+            assert_eq!(cache.into_inner().reads, reads);
+        }
+    }
+
+    #[test]
+    fn test_write_through() {
+        use super::{Cache, Eviction, Mode, Slot};
+        use crate::block::{
+            test::{block_on, Memory},
+            Device,
+        };
+
+        let mut memory = Memory::<8>::default();
+        let mut slots = [const { Slot::new() }; 2];
+        let mut cache = Cache::new(&mut memory, &mut slots, Eviction::LRU, Mode::WriteThrough);
+        let mut block = [0u8; 512];
+        block_on(cache.read(1, core::iter::once(&mut block))).unwrap();
+        for address in [1, 2] {
+            let block = [address as u8; 512];
+            block_on(cache.write(address, core::iter::once(&block))).unwrap();
+        }
+        block_on(cache.read(1, core::iter::once(&mut block))).unwrap();
+        assert_eq!(block, [1u8; 512]);
+        let memory = cache.into_inner();
+        assert_eq!((memory.reads, memory.writes), (1, 2));
+        assert_eq!((memory.blocks[1], memory.blocks[2]), ([1u8; 512], [2u8; 512]));
+
+        // Failed write leaves neither device nor cache modified
+        memory.read_only = true;
+        let mut cache = Cache::new(memory, &mut slots, Eviction::LRU, Mode::WriteThrough);
+        block_on(cache.read(1, core::iter::once(&mut block))).unwrap();
+        assert!(block_on(cache.write(1, core::iter::once(&[0xFF; 512]))).is_err());
+        block_on(cache.read(1, core::iter::once(&mut block))).unwrap();
+        assert_eq!(block, [1u8; 512]);
+    }
+}
@@ -0,0 +1,33 @@
+use aligned::{Aligned, A1};
+use block_device_driver::BlockDevice;
+
+use crate::{bus, bus::Error, sd::BLOCK_SIZE, SD};
+
+impl<E, BUS> BlockDevice<BLOCK_SIZE> for SD<BUS>
+where
+    BUS: bus::Read<Error = E> + bus::Write<Error = E> + bus::Bus<Error = E>,
+    E: core::fmt::Debug,
+{
+    type Error = Error<E>;
+    type Align = A1;
+
+    async fn read(
+        &mut self,
+        block_address: u32,
+        data: &mut [Aligned<A1, [u8; BLOCK_SIZE]>],
+    ) -> Result<(), Error<E>> {
+        SD::read(self, block_address, data.iter_mut().map(|block| &mut **block)).await
+    }
+
+    async fn write(
+        &mut self,
+        block_address: u32,
+        data: &[Aligned<A1, [u8; BLOCK_SIZE]>],
+    ) -> Result<(), Error<E>> {
+        SD::write(self, block_address, data.iter().map(|block| &**block)).await
+    }
+
+    async fn size(&mut self) -> Result<u64, Error<E>> {
+        Ok(self.csd.capacity())
+    }
+}
@@ -0,0 +1,98 @@
+use embedded_io::ErrorKind;
+
+use crate::{
+    block::Device,
+    bus::Error,
+    stream::{self, Stream},
+};
+
+impl<E: core::fmt::Debug + core::fmt::Display> embedded_io::Error for Error<E> {
+    fn kind(&self) -> ErrorKind {
+        io_kind!(Error::kind(self), ErrorKind)
+    }
+}
+
+impl<E: embedded_io::Error> embedded_io::Error for stream::Error<E> {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Device(e) => e.kind(),
+            Self::InvalidSeek => ErrorKind::InvalidInput,
+        }
+    }
+}
+
+impl<D: Device<Error: embedded_io::Error>> embedded_io::ErrorType for Stream<D> {
+    type Error = stream::Error<D::Error>;
+}
+
+#[cfg(all(feature = "embedded-io", not(feature = "async")))]
+mod blocking {
+    use embedded_io::{Read, Seek, SeekFrom, Write};
+
+    use crate::{
+        block::Device,
+        stream::{Error, Stream},
+    };
+
+    impl<D: Device<Error: embedded_io::Error>> Read for Stream<D> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error<D::Error>> {
+            Stream::read(self, buf)
+        }
+    }
+
+    impl<D: Device<Error: embedded_io::Error>> Write for Stream<D> {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Error<D::Error>> {
+            Stream::write(self, buf)
+        }
+
+        fn flush(&mut self) -> Result<(), Error<D::Error>> {
+            Stream::flush(self)
+        }
+    }
+
+    impl<D: Device<Error: embedded_io::Error>> Seek for Stream<D> {
+        fn seek(&mut self, pos: SeekFrom) -> Result<u64, Error<D::Error>> {
+            match pos {
+                SeekFrom::Start(position) => self.seek_from(position, 0),
+                SeekFrom::End(offset) => self.seek_from(self.len(), offset),
+                SeekFrom::Current(offset) => self.seek_from(self.position(), offset),
+            }
+        }
+    }
+}
+
+#[cfg(all(feature = "embedded-io-async", feature = "async"))]
+mod nonblocking {
+    use embedded_io_async::{Read, Seek, SeekFrom, Write};
+
+    use crate::{
+        block::Device,
+        stream::{Error, Stream},
+    };
+
+    impl<D: Device<Error: embedded_io::Error>> Read for Stream<D> {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error<D::Error>> {
+            Stream::read(self, buf).await
+        }
+    }
+
+    impl<D: Device<Error: embedded_io::Error>> Write for Stream<D> {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Error<D::Error>> {
+            Stream::write(self, buf).await
+        }
+
+        async fn flush(&mut self) -> Result<(), Error<D::Error>> {
+            Stream::flush(self).await
+        }
+    }
+
+    impl<D: Device<Error: embedded_io::Error>> Seek for Stream<D> {
+        async fn seek(&mut self, pos: SeekFrom) -> Result<u64, Error<D::Error>> {
+            match pos {
+                SeekFrom::Start(position) => self.seek_from(position, 0),
+                SeekFrom::End(offset) => self.seek_from(self.len(), offset),
+                SeekFrom::Current(offset) => self.seek_from(self.position(), offset),
+            }
+        }
+    }
+}
@@ -0,0 +1,36 @@
+use ::embedded_sdmmc::{Block, BlockCount, BlockDevice, BlockIdx};
+
+use crate::{
+    bus::{self, Error, ErrorKind},
+    SD,
+};
+
+impl<E, BUS> BlockDevice for SD<BUS>
+where
+    BUS: bus::Read<Error = E> + bus::Write<Error = E> + bus::Bus<Error = E>,
+    Error<E>: core::error::Error + 'static,
+{
+    type Error = Error<E>;
+
+    fn read(&self, blocks: &mut [Block], start_block_idx: BlockIdx) -> Result<(), Error<E>> {
+        if self.locked {
+            return Err(ErrorKind::Locked.into());
+        }
+        let mut bus = self.bus.borrow_mut();
+        let blocks = blocks.iter_mut().map(|block| &mut block.contents);
+        crate::read(&mut *bus, &mut self.stats.borrow_mut(), self.card, start_block_idx.0, blocks)
+    }
+
+    fn write(&self, blocks: &[Block], start_block_idx: BlockIdx) -> Result<(), Error<E>> {
+        if self.locked {
+            return Err(ErrorKind::Locked.into());
+        }
+        let mut bus = self.bus.borrow_mut();
+        let blocks = blocks.iter().map(|block| &block.contents);
+        crate::write(&mut *bus, &mut self.stats.borrow_mut(), self.card, start_block_idx.0, blocks)
+    }
+
+    fn num_blocks(&self) -> Result<BlockCount, Error<E>> {
+        Ok(BlockCount((self.csd.capacity() / Block::LEN as u64) as u32))
+    }
+}
@@ -0,0 +1,41 @@
+#[cfg(all(feature = "embedded-sdmmc", feature = "async"))]
+compile_error!("embedded-sdmmc is blocking only, disable default features to drop async");
+
+/// `io_kind!(kind, ErrorKind)` maps `bus::ErrorKind` to `ErrorKind` of
+/// `std::io` or `embedded_io`, which share variant names
+#[allow(unused_macros)]
+macro_rules! io_kind {
+    ($kind:expr, $ErrorKind:ident) => {{
+        use crate::{
+            bus,
+            sd::{response::R1Status, transfer::TokenError},
+        };
+        match $kind {
+            bus::ErrorKind::NoResponse => $ErrorKind::NotConnected,
+            bus::ErrorKind::Command(r1)
+                if r1.has(R1Status::AddressError) || r1.has(R1Status::ParameterError) =>
+            {
+                $ErrorKind::InvalidInput
+            }
+            bus::ErrorKind::Transfer(TokenError::OutOfRange) => $ErrorKind::InvalidInput,
+            bus::ErrorKind::Transfer(TokenError::CardLocked) | bus::ErrorKind::Locked => {
+                $ErrorKind::PermissionDenied
+            }
+            bus::ErrorKind::LockUnlockFailed => $ErrorKind::PermissionDenied,
+            bus::ErrorKind::PasswordTooLong => $ErrorKind::InvalidInput,
+            bus::ErrorKind::Timeout => $ErrorKind::TimedOut,
+            bus::ErrorKind::Unsupported => $ErrorKind::Unsupported,
+            _ => $ErrorKind::Other,
+        }
+    }};
+}
+
+#[cfg(all(feature = "block-device-driver", feature = "async"))]
+mod block_device_driver;
+#[cfg(any(feature = "embedded-io", feature = "embedded-io-async"))]
+mod embedded_io;
+#[cfg(all(feature = "embedded-sdmmc", not(feature = "async")))]
+mod embedded_sdmmc;
+// `std::io` impls are only compiled in blocking mode, `not(feature = "async")`
+#[cfg(all(feature = "std", not(feature = "async")))]
+mod std_io;
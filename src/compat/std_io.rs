@@ -0,0 +1,53 @@
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+
+use crate::{
+    block::Device,
+    bus::Error,
+    stream::{self, Stream},
+};
+
+impl<E> From<Error<E>> for io::Error
+where
+    Error<E>: std::error::Error + Send + Sync + 'static,
+{
+    fn from(error: Error<E>) -> Self {
+        let kind = io_kind!(error.kind(), ErrorKind);
+        io::Error::new(kind, error)
+    }
+}
+
+impl<E: Into<io::Error>> From<stream::Error<E>> for io::Error {
+    fn from(error: stream::Error<E>) -> Self {
+        match error {
+            stream::Error::Device(e) => e.into(),
+            stream::Error::InvalidSeek => io::Error::new(ErrorKind::InvalidInput, "invalid seek"),
+        }
+    }
+}
+
+impl<D: Device<Error: Into<io::Error>>> Read for Stream<D> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Ok(Stream::read(self, buf)?)
+    }
+}
+
+impl<D: Device<Error: Into<io::Error>>> Write for Stream<D> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(Stream::write(self, buf)?)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(Stream::flush(self)?)
+    }
+}
+
+impl<D: Device<Error: Into<io::Error>>> Seek for Stream<D> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let result = match pos {
+            SeekFrom::Start(position) => self.seek_from(position, 0),
+            SeekFrom::End(offset) => self.seek_from(self.len(), offset),
+            SeekFrom::Current(offset) => self.seek_from(self.position(), offset),
+        };
+        Ok(result?)
+    }
+}
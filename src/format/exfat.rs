@@ -0,0 +1,191 @@
+use core::iter;
+
+use super::{clear, Error, Geometry, Options};
+use crate::{block::Device, sd::BLOCK_SIZE};
+
+/// Main boot region, followed by backup boot region of the same size
+const BOOT_REGION: u32 = 12;
+const FIRST_CLUSTER: u32 = 2;
+const END_OF_CHAIN: u32 = 0xFFFF_FFFF;
+/// Compressed up-case table mapping ASCII lowercase letters only: an identity run
+/// of 0x61 characters, 'A' to 'Z', then identity for the rest
+const UPCASE: [u16; 30] = {
+    let mut table = [0xFFFF; 30];
+    table[1] = 0x61;
+    let mut i = 0;
+    while i < 26 {
+        table[2 + i] = b'A' as u16 + i as u16;
+        i += 1;
+    }
+    table[29] = (0x10000 - 0x7B) as u16;
+    table
+};
+
+fn put(bytes: &mut [u8], offset: usize, value: &[u8]) {
+    bytes[offset..offset + value.len()].copy_from_slice(value);
+}
+
+fn checksum(checksum: u32, bytes: impl Iterator<Item = u8>) -> u32 {
+    bytes.fold(checksum, |sum, byte| sum.rotate_right(1).wrapping_add(byte as u32))
+}
+
+#[cfg_attr(not(feature = "async"), deasync::deasync)]
+pub(super) async fn format<D>(
+    device: &mut D,
+    start: u32,
+    num_blocks: u32,
+    geometry: &Geometry,
+    options: &Options<'_>,
+) -> Result<(), Error<D::Error>>
+where
+    D: Device,
+{
+    let (cluster, boundary) = (geometry.cluster, geometry.boundary);
+    // FAT from middle of first boundary unit, cluster heap on following boundary
+    let fat_offset = boundary / 2;
+    let fat_blocks = ((num_blocks / cluster) as u64 + 2) * 4;
+    let fat_blocks = fat_blocks.div_ceil(BLOCK_SIZE as u64) as u32;
+    let heap = (fat_offset + fat_blocks).next_multiple_of(boundary);
+    let fat_length = heap - fat_offset;
+    let clusters = num_blocks.saturating_sub(heap) / cluster;
+    if clusters == 0 {
+        return Err(Error::Capacity);
+    }
+    let bitmap_bytes = clusters.div_ceil(8);
+    let bitmap_clusters = bitmap_bytes.div_ceil(cluster * BLOCK_SIZE as u32);
+    let upcase_cluster = FIRST_CLUSTER + bitmap_clusters;
+    let root_cluster = upcase_cluster + 1;
+    let used = bitmap_clusters + 2;
+    let cluster_address = |index: u32| start + heap + (index - FIRST_CLUSTER) * cluster;
+
+    let mut blocks = [[0u8; BLOCK_SIZE]; BOOT_REGION as usize];
+    let boot = &mut blocks[0];
+    put(boot, 0, &[0xEB, 0x76, 0x90]);
+    put(boot, 3, b"EXFAT   ");
+    put(boot, 64, &(start as u64).to_le_bytes());
+    put(boot, 72, &(num_blocks as u64).to_le_bytes());
+    put(boot, 80, &fat_offset.to_le_bytes());
+    put(boot, 84, &fat_length.to_le_bytes());
+    put(boot, 88, &heap.to_le_bytes());
+    put(boot, 92, &clusters.to_le_bytes());
+    put(boot, 96, &root_cluster.to_le_bytes());
+    put(boot, 100, &options.volume_id.to_le_bytes());
+    put(boot, 104, &0x0100u16.to_le_bytes()); // Revision 1.0
+    boot[108] = BLOCK_SIZE.trailing_zeros() as u8;
+    boot[109] = cluster.trailing_zeros() as u8;
+    boot[110] = 1; // Number of FATs
+    boot[111] = 0x80; // Drive select
+    boot[112] = 0xFF; // Percent in use not available
+    put(boot, 510, &[0x55, 0xAA]);
+    for block in blocks[1..9].iter_mut() {
+        put(block, 508, &[0x00, 0x00, 0x55, 0xAA]);
+    }
+    // Volume flags and percent in use are excluded from boot checksum
+    let bytes = blocks[..11].iter().flatten().enumerate();
+    let bytes = bytes.filter(|&(i, _)| !matches!(i, 106 | 107 | 112)).map(|(_, &byte)| byte);
+    let sum = checksum(0, bytes);
+    blocks[11].chunks_mut(4).for_each(|chunk| chunk.copy_from_slice(&sum.to_le_bytes()));
+    device.write(start, blocks.iter()).await?;
+    device.write(start + BOOT_REGION, blocks.iter()).await?;
+    clear(device, start + 2 * BOOT_REGION, fat_offset - 2 * BOOT_REGION).await?;
+
+    // FAT chains of allocation bitmap, up-case table and root directory
+    let entry = |index: u32| match index {
+        0 => 0xFFFF_FFF8,
+        1 => END_OF_CHAIN,
+        index if index + 1 < upcase_cluster => index + 1,
+        _ => END_OF_CHAIN,
+    };
+    let mut address = start + fat_offset;
+    let entries_per_block = (BLOCK_SIZE / 4) as u32;
+    for first in (0..FIRST_CLUSTER + used).step_by(entries_per_block as usize) {
+        let mut block = [0u8; BLOCK_SIZE];
+        for (i, bytes) in block.chunks_mut(4).enumerate() {
+            let index = first + i as u32;
+            if index < FIRST_CLUSTER + used {
+                bytes.copy_from_slice(&entry(index).to_le_bytes());
+            }
+        }
+        device.write(address, iter::once(&block)).await?;
+        address += 1;
+    }
+    clear(device, address, start + heap - address).await?;
+
+    let bitmap = cluster_address(FIRST_CLUSTER);
+    clear(device, bitmap, bitmap_clusters * cluster).await?;
+    let mut block = [0u8; BLOCK_SIZE];
+    for index in 0..used {
+        block[index as usize / 8] |= 1 << (index % 8);
+    }
+    device.write(bitmap, iter::once(&block)).await?;
+
+    let mut block = [0u8; BLOCK_SIZE];
+    UPCASE.iter().enumerate().for_each(|(i, c)| put(&mut block, i * 2, &c.to_le_bytes()));
+    let upcase_bytes = UPCASE.len() * 2;
+    clear(device, cluster_address(upcase_cluster), cluster).await?;
+    device.write(cluster_address(upcase_cluster), iter::once(&block)).await?;
+
+    let mut block = [0u8; BLOCK_SIZE];
+    let mut entries = block.chunks_mut(32);
+    let label = options.label.encode_utf16().take(11);
+    let count = label.clone().count();
+    if let Some(entry) = (count > 0).then(|| entries.next()).flatten() {
+        entry[0] = 0x83;
+        entry[1] = count as u8;
+        label.enumerate().for_each(|(i, c)| put(entry, 2 + i * 2, &c.to_le_bytes()));
+    }
+    if let Some(entry) = entries.next() {
+        entry[0] = 0x81;
+        put(entry, 20, &FIRST_CLUSTER.to_le_bytes());
+        put(entry, 24, &(bitmap_bytes as u64).to_le_bytes());
+    }
+    if let Some(entry) = entries.next() {
+        entry[0] = 0x82;
+        put(entry, 4, &checksum(0, utf16_bytes(&UPCASE)).to_le_bytes());
+        put(entry, 20, &upcase_cluster.to_le_bytes());
+        put(entry, 24, &(upcase_bytes as u64).to_le_bytes());
+    }
+    clear(device, cluster_address(root_cluster), cluster).await?;
+    device.write(cluster_address(root_cluster), iter::once(&block)).await?;
+    Ok(())
+}
+
+fn utf16_bytes(table: &[u16]) -> impl Iterator<Item = u8> + '_ {
+    table.iter().flat_map(|c| c.to_le_bytes())
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_format() {
+        use super::checksum;
+        use crate::block::test::{block_on, Sparse};
+        use crate::format::{format, FileSystem, Options};
+
+        let mut device = Sparse::new(128 * 1024 * 1024); // 64GB
+        let options = Options { label: "", volume_id: 0x1234 };
+        assert_eq!(block_on(format(&mut device, 8192, options)).unwrap(), FileSystem::ExFAT);
+        assert_eq!(device.block(0)[450], 0x07);
+
+        let start = 32768;
+        let boot = device.block(start);
+        let word = |offset: usize| u32::from_le_bytes(boot[offset..offset + 4].try_into().unwrap());
+        let (fat_offset, heap, root) = (word(80), word(88), word(96));
+        assert_eq!(
+            (&boot[3..11], fat_offset, (start + heap) % 32768),
+            (&b"EXFAT   "[..], 16384, 0)
+        );
+
+        let region: [_; 11] = core::array::from_fn(|i| device.block(start + i as u32));
+        let bytes = region.iter().flatten().enumerate();
+        let bytes = bytes.filter(|&(i, _)| !matches!(i, 106 | 107 | 112)).map(|(_, &byte)| byte);
+        let sum = checksum(0, bytes).to_le_bytes();
+        assert_eq!(device.block(start + 11)[..8], [sum, sum].concat());
+        assert_eq!(device.block(start + 12), boot);
+
+        let fat = device.block(start + fat_offset);
+        assert_eq!(fat[8..20], [0xFF; 12]); // Bitmap, up-case table and root directory
+        let directory = device.block(start + heap + (root - 2) * 256);
+        assert_eq!((directory[0], directory[32], directory[64]), (0x81, 0x82, 0));
+    }
+}
@@ -0,0 +1,144 @@
+use core::iter;
+
+use super::{clear, Error, Geometry, Options};
+use crate::{block::Device, sd::BLOCK_SIZE};
+
+const MIN_CLUSTERS: u32 = 65525;
+const MAX_CLUSTERS: u32 = 0x0FFF_FFF5;
+const RESERVED: u32 = 32;
+const ROOT_CLUSTER: u32 = 2;
+
+fn put(bytes: &mut [u8], offset: usize, value: &[u8]) {
+    bytes[offset..offset + value.len()].copy_from_slice(value);
+}
+
+/// Reserved blocks, blocks per FAT and number of clusters, with data area on boundary
+fn layout(start: u32, num_blocks: u32, cluster: u32, boundary: u32) -> (u32, u32, u32) {
+    let entries_blocks = |clusters: u64| ((clusters + 2) * 4).div_ceil(BLOCK_SIZE as u64) as u32;
+    let mut fat = entries_blocks((num_blocks / cluster) as u64);
+    loop {
+        let data = (start + RESERVED + 2 * fat).next_multiple_of(boundary) - start;
+        let clusters = num_blocks.saturating_sub(data) / cluster;
+        let needed = entries_blocks(clusters as u64);
+        if needed <= fat {
+            return (data - 2 * fat, fat, clusters);
+        }
+        fat = needed;
+    }
+}
+
+fn label(options: &Options<'_>) -> [u8; 11] {
+    let mut label = [b' '; 11];
+    let chars =
+        options.label.bytes().map(|b| if b.is_ascii() { b.to_ascii_uppercase() } else { b'_' });
+    label.iter_mut().zip(chars).for_each(|(l, c)| *l = c);
+    label
+}
+
+#[cfg_attr(not(feature = "async"), deasync::deasync)]
+pub(super) async fn format<D>(
+    device: &mut D,
+    start: u32,
+    num_blocks: u32,
+    geometry: &Geometry,
+    options: &Options<'_>,
+) -> Result<(), Error<D::Error>>
+where
+    D: Device,
+{
+    // Smaller clusters for cards below 4GB, where 32KB ones would be too few
+    let mut cluster = geometry.cluster;
+    let (mut reserved, mut fat, mut clusters) =
+        layout(start, num_blocks, cluster, geometry.boundary);
+    while clusters < MIN_CLUSTERS && cluster > 1 {
+        cluster /= 2;
+        (reserved, fat, clusters) = layout(start, num_blocks, cluster, geometry.boundary);
+    }
+    // Reserved blocks pad FAT up to boundary, which a large allocation unit may overflow
+    if !(MIN_CLUSTERS..=MAX_CLUSTERS).contains(&clusters) || reserved > u16::MAX as u32 {
+        return Err(Error::Capacity);
+    }
+    let label = if options.label.is_empty() { *b"NO NAME    " } else { label(options) };
+
+    let mut block = [0u8; BLOCK_SIZE];
+    put(&mut block, 0, &[0xEB, 0x58, 0x90]);
+    put(&mut block, 3, b"MSWIN4.1");
+    put(&mut block, 11, &(BLOCK_SIZE as u16).to_le_bytes());
+    block[13] = cluster as u8;
+    put(&mut block, 14, &(reserved as u16).to_le_bytes());
+    block[16] = 2; // Number of FATs
+    block[21] = 0xF8; // Media
+    put(&mut block, 24, &63u16.to_le_bytes()); // Sectors per track
+    put(&mut block, 26, &255u16.to_le_bytes()); // Heads
+    put(&mut block, 28, &start.to_le_bytes()); // Hidden sectors
+    put(&mut block, 32, &(reserved + 2 * fat + clusters * cluster).to_le_bytes());
+    put(&mut block, 36, &fat.to_le_bytes());
+    put(&mut block, 44, &ROOT_CLUSTER.to_le_bytes());
+    put(&mut block, 48, &1u16.to_le_bytes()); // FSInfo
+    put(&mut block, 50, &6u16.to_le_bytes()); // Backup boot sector
+    block[64] = 0x80; // Drive number
+    block[66] = 0x29; // Extended boot signature
+    put(&mut block, 67, &options.volume_id.to_le_bytes());
+    put(&mut block, 71, &label);
+    put(&mut block, 82, b"FAT32   ");
+    put(&mut block, 510, &[0x55, 0xAA]);
+    clear(device, start, reserved).await?;
+    device.write(start, iter::once(&block)).await?;
+    device.write(start + 6, iter::once(&block)).await?;
+
+    block.fill(0);
+    put(&mut block, 0, &0x41615252u32.to_le_bytes());
+    put(&mut block, 484, &0x61417272u32.to_le_bytes());
+    put(&mut block, 488, &(clusters - 1).to_le_bytes()); // Free clusters
+    put(&mut block, 492, &(ROOT_CLUSTER + 1).to_le_bytes()); // Next free cluster
+    put(&mut block, 508, &[0x00, 0x00, 0x55, 0xAA]);
+    device.write(start + 1, iter::once(&block)).await?;
+    device.write(start + 7, iter::once(&block)).await?;
+
+    block.fill(0);
+    put(&mut block, 0, &0x0FFFFFF8u32.to_le_bytes());
+    put(&mut block, 4, &0x0FFFFFFFu32.to_le_bytes());
+    put(&mut block, 8, &0x0FFFFFFFu32.to_le_bytes()); // Root directory
+    for address in [start + reserved, start + reserved + fat] {
+        device.write(address, iter::once(&block)).await?;
+        clear(device, address + 1, fat - 1).await?;
+    }
+
+    let root = start + reserved + 2 * fat;
+    clear(device, root, cluster).await?;
+    if !options.label.is_empty() {
+        block.fill(0);
+        put(&mut block, 0, &label);
+        block[11] = 0x08; // Volume label attribute
+        device.write(root, iter::once(&block)).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_format() {
+        use crate::block::test::{block_on, Sparse};
+        use crate::format::{format, FileSystem, Options};
+
+        let mut device = Sparse::new(16 * 1024 * 1024); // 8GB
+        let options = Options { label: "sdcard", volume_id: 0x1234 };
+        assert_eq!(block_on(format(&mut device, 8192, options)).unwrap(), FileSystem::FAT32);
+        let mbr = device.block(0);
+        assert_eq!((mbr[450], &mbr[454..458]), (0x0C, &8192u32.to_le_bytes()[..]));
+
+        let boot = device.block(8192);
+        let word = |offset: usize| u32::from_le_bytes(boot[offset..offset + 4].try_into().unwrap());
+        let (reserved, fat) = (u16::from_le_bytes([boot[14], boot[15]]) as u32, word(36));
+        assert_eq!((boot[13], (8192 + reserved + 2 * fat) % 8192), (64, 0));
+        assert_eq!(&boot[71..82], b"SDCARD     ");
+        assert_eq!(device.block(8192 + 6), boot);
+        assert_eq!(device.block(8192 + reserved)[8..12], [0xFF, 0xFF, 0xFF, 0x0F]);
+
+        // 64MB allocation unit leaves more reserved blocks than BPB can tell
+        let mut device = Sparse::new(16 * 1024 * 1024);
+        let result = block_on(format(&mut device, 131072, options));
+        assert!(matches!(result, Err(crate::format::Error::Capacity)));
+    }
+}
@@ -0,0 +1,119 @@
+mod exfat;
+mod fat32;
+
+use core::iter;
+
+use derive_more::Display;
+use thiserror::Error;
+
+use crate::{
+    block::Device,
+    partition::{self, mbr},
+    sd::BLOCK_SIZE,
+};
+
+#[derive(Debug, Error, Display)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<E> {
+    #[display("{_0}")]
+    Device(E),
+    /// Too small for FAT32, too large for MBR,
+    /// or allocation unit too large for FAT32 reserved area
+    #[display("unsupported capacity")]
+    Capacity,
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(error: E) -> Self {
+        Self::Device(error)
+    }
+}
+
+impl<E> From<partition::Error<E>> for Error<E> {
+    fn from(error: partition::Error<E>) -> Self {
+        match error {
+            partition::Error::Device(error) => Self::Device(error),
+            _ => Self::Capacity,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FileSystem {
+    FAT32,
+    ExFAT,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Options<'a> {
+    /// Up to 11 characters, ASCII only for FAT32
+    pub label: &'a str,
+    pub volume_id: u32,
+}
+
+/// Sizes in blocks
+struct Geometry {
+    file_system: FileSystem,
+    cluster: u32,
+    /// Partition, cluster heap and FAT are aligned to boundary unit
+    boundary: u32,
+}
+
+/// As recommended by SD Association's file system specification
+fn geometry(num_blocks: u32) -> Geometry {
+    let (file_system, cluster, boundary) = match num_blocks {
+        0..=0x400_0000 => (FileSystem::FAT32, 64, 8192), // SDHC up to 32GB
+        0x400_0001..=0x800_0000 => (FileSystem::ExFAT, 256, 32768),
+        0x800_0001..=0x1000_0000 => (FileSystem::ExFAT, 256, 65536),
+        0x1000_0001..=0x2000_0000 => (FileSystem::ExFAT, 256, 131072),
+        0x2000_0001..=0x4000_0000 => (FileSystem::ExFAT, 512, 262144),
+        0x4000_0001..=0x8000_0000 => (FileSystem::ExFAT, 512, 524288),
+        _ => (FileSystem::ExFAT, 512, 1048576),
+    };
+    Geometry { file_system, cluster, boundary }
+}
+
+static ZERO: [u8; BLOCK_SIZE] = [0; BLOCK_SIZE];
+
+#[cfg_attr(not(feature = "async"), deasync::deasync)]
+async fn clear<D: Device>(device: &mut D, address: u32, num_blocks: u32) -> Result<(), D::Error> {
+    device.write(address, iter::repeat_n(&ZERO, num_blocks as usize)).await
+}
+
+/// Formats whole device as a single partition, FAT32 up to 32GB and exFAT beyond,
+/// with partition, FAT and clusters aligned to larger one of `allocation_unit`
+/// (see [`SD::allocation_unit`](crate::SD::allocation_unit)) and recommended boundary unit
+///
+/// Unlike SD Association's specification, which calls for FAT12 or FAT16 on SDSC cards
+/// up to 2GB, those get FAT32 as well, falling back to smaller clusters, and fail with
+/// [`Error::Capacity`] if still too small for FAT32 minimum of 65525 clusters.
+#[cfg_attr(not(feature = "async"), deasync::deasync)]
+pub async fn format<D>(
+    device: &mut D,
+    allocation_unit: u32,
+    options: Options<'_>,
+) -> Result<FileSystem, Error<D::Error>>
+where
+    D: Device,
+{
+    let mut geometry = geometry(device.num_blocks());
+    geometry.boundary = geometry.boundary.max(allocation_unit);
+    let (start, num_blocks) =
+        (geometry.boundary, device.num_blocks().saturating_sub(geometry.boundary));
+    let kind = match geometry.file_system {
+        FileSystem::FAT32 => {
+            fat32::format(device, start, num_blocks, &geometry, &options).await?;
+            0x0C
+        }
+        FileSystem::ExFAT => {
+            exfat::format(device, start, num_blocks, &geometry, &options).await?;
+            0x07
+        }
+    };
+    // Partition table written last, so that an interrupted format leaves nothing mountable
+    let mut partitions = [mbr::Entry { kind, start, num_blocks, ..Default::default() }];
+    mbr::create(device, &mut partitions, 1).await?;
+    Ok(geometry.file_system)
+}
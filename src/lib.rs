@@ -1,15 +1,24 @@
 #![doc = include_str!("../README.md")]
 #![doc(hidden)]
 #![cfg_attr(not(any(test, feature = "std")), no_std)]
+#![allow(clippy::upper_case_acronyms)]
 
 extern crate alloc;
-#[cfg(feature = "logging")]
+#[cfg(all(feature = "logging", not(feature = "defmt")))]
 #[macro_use]
 extern crate log;
 #[cfg(feature = "spidev")]
 extern crate spidev;
 
-#[cfg(not(feature = "logging"))]
+#[cfg(feature = "defmt")]
+#[macro_use]
+mod logging {
+    macro_rules! trace {
+        ($($arg:tt)*) => { defmt::trace!($($arg)*) };
+    }
+}
+
+#[cfg(not(any(feature = "logging", feature = "defmt")))]
 #[macro_use]
 mod logging {
     #[macro_export]
@@ -18,18 +27,42 @@ mod logging {
     }
 }
 
+#[macro_use]
+mod span;
+
+pub mod block;
 pub mod bus;
+pub mod cache;
+mod compat;
 pub mod delay;
+pub mod format;
+pub mod partition;
+pub mod readahead;
 mod sd;
+pub mod stats;
+pub mod stream;
 
-use bus::Error;
-pub use sd::registers::NumBlocks;
-use sd::{registers::CSD, BLOCK_SIZE};
+use core::cell::RefCell;
+
+#[cfg(feature = "tracing")]
+use tracing::field::Empty;
+
+use bus::{Error, ErrorKind};
+use sd::registers::CSD;
+pub use sd::{
+    command::LockUnlock,
+    registers::{NumBlocks, SDStatus, SCR},
+    response::{R1Status, R1, R2},
+    BLOCK_SIZE,
+};
 
 pub struct SD<BUS> {
-    bus: BUS,
+    // Only borrowed at runtime by interfaces taking `&self`, e.g. embedded-sdmmc
+    bus: RefCell<BUS>,
     card: sd::Card,
     csd: CSD,
+    locked: bool,
+    stats: RefCell<stats::Statistics>,
 }
 
 type LBA = u32;
@@ -39,45 +72,173 @@ impl<E, BUS> SD<BUS>
 where
     BUS: bus::Read<Error = E> + bus::Write<Error = E> + bus::Bus<Error = E>,
 {
-    pub async fn init(mut bus: BUS, card: sd::Card) -> Result<Self, Error<E>> {
+    /// Locked card opens as well, see [`SD::locked`]
+    pub async fn init(bus: BUS, card: sd::Card) -> Result<Self, Error<E>> {
+        traced!(
+            tracing::info_span!("init", ?card, duration_us = Empty, outcome = Empty),
+            Self::open(bus, card)
+        )
+    }
+
+    async fn open(mut bus: BUS, card: sd::Card) -> Result<Self, Error<E>> {
         bus.before()?;
-        let result = bus.read_csd().await;
+        let result = match bus.read_csd().await {
+            Ok(csd) => match bus.read_status().await {
+                Ok(status) => Ok((csd, status.card_locked())),
+                // Card reports lock itself on access if bus can't tell
+                Err(e) if matches!(e.kind(), ErrorKind::Unsupported) => Ok((csd, false)),
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e),
+        };
         bus.after()?;
-        result.map(|csd| Self { bus, card, csd })
+        let (csd, locked) = result?;
+        let stats = RefCell::new(stats::Statistics::default());
+        Ok(Self { bus: RefCell::new(bus), card, csd, locked, stats })
+    }
+
+    /// Locked by password, reads and writes fail with [`ErrorKind::Locked`]
+    /// until unlocked with [`SD::lock_unlock`]
+    pub fn locked(&self) -> bool {
+        self.locked
     }
 
     pub fn csd(&self) -> CSD {
         self.csd
     }
 
+    pub async fn sd_status(&mut self) -> Result<SDStatus, Error<E>> {
+        let bus = self.bus.get_mut();
+        bus.before()?;
+        let result = bus.read_sd_status().await;
+        self.stats.get_mut().count(&result);
+        bus.after().and(result)
+    }
+
+    pub async fn lock_unlock(&mut self, command: LockUnlock<'_>) -> Result<(), Error<E>> {
+        let (bus, stats) = (self.bus.get_mut(), self.stats.get_mut());
+        bus.before()?;
+        let start = bus.uptime();
+        let result = bus.lock_unlock(command).await;
+        if let (LockUnlock::ForceErase, Ok(_)) = (command, &result) {
+            stats.erase.record_since(start, bus.uptime());
+        }
+        stats.count(&result);
+        let status = match result.is_ok() {
+            true => bus.read_status().await.ok(),
+            false => None,
+        };
+        bus.after().and(result)?;
+        self.locked = match (status, command) {
+            (Some(status), _) => status.card_locked(),
+            (None, LockUnlock::Lock(_)) => true,
+            (None, LockUnlock::Unlock(_) | LockUnlock::ForceErase) => false,
+            (None, _) => self.locked,
+        };
+        Ok(())
+    }
+
+    /// Write protect group in blocks, `None` if not supported as for SDHC and above
+    pub fn write_protect_group_size(&self) -> Option<u32> {
+        self.csd.write_protect_group_bytes().map(|bytes| bytes / BLOCK_SIZE as u32)
+    }
+
+    /// Sets or clears write protection of group containing block `address`
+    pub async fn set_write_protect(&mut self, address: LBA, protect: bool) -> Result<(), Error<E>> {
+        let bus = self.bus.get_mut();
+        bus.before()?;
+        let result = bus.set_write_protect(card_address(self.card, address), protect).await;
+        self.stats.get_mut().count(&result);
+        bus.after().and(result.map_err(|e| e.with_address(address)))
+    }
+
+    /// Write protection of 32 groups from group containing block `address`,
+    /// bit 0 for the first group
+    pub async fn write_protect(&mut self, address: LBA) -> Result<u32, Error<E>> {
+        let bus = self.bus.get_mut();
+        bus.before()?;
+        let result = bus.read_write_protect(card_address(self.card, address)).await;
+        self.stats.get_mut().count(&result);
+        bus.after().and(result.map_err(|e| e.with_address(address)))
+    }
+
+    /// Programs COPY and TMP_WRITE_PROTECT bits of CSD,
+    /// temporary write protection covering the whole card
+    pub async fn program_csd(&mut self, copy: bool, write_protect: bool) -> Result<(), Error<E>> {
+        let csd = self.csd.with_flags(copy, write_protect);
+        let bus = self.bus.get_mut();
+        bus.before()?;
+        let result = bus.program_csd(csd).await;
+        self.stats.get_mut().count(&result);
+        bus.after().and(result)?;
+        self.csd = csd;
+        Ok(())
+    }
+
+    /// Allocation unit in blocks, which partitions and filesystems should align to,
+    /// falls back to erase sector size if not defined in SD Status
+    /// or SD Status unsupported by the bus
+    pub async fn allocation_unit(&mut self) -> Result<u32, Error<E>> {
+        let au_bytes = match self.sd_status().await {
+            Ok(status) => status.au_bytes(),
+            Err(e) if matches!(e.kind(), ErrorKind::Unsupported) => None,
+            Err(e) => return Err(e),
+        };
+        Ok(au_bytes.unwrap_or(self.csd.erase_sector_bytes()) / BLOCK_SIZE as u32)
+    }
+
     pub fn bus<R>(&mut self, f: impl Fn(&mut BUS) -> R) -> R {
-        f(&mut self.bus)
+        f(self.bus.get_mut())
     }
 
     pub async fn read<'a, B>(&mut self, address: LBA, blocks: B) -> Result<(), Error<E>>
     where
         B: core::iter::ExactSizeIterator<Item = &'a mut [u8; BLOCK_SIZE]>,
     {
-        if blocks.len() == 0 {
-            return Ok(());
+        if self.locked {
+            return Err(ErrorKind::Locked.into());
         }
-        self.bus.before()?;
-        let address = if self.card.high_capacity() { address } else { address * BLOCK_SIZE as u32 };
-        let result = self.bus.read(address, blocks).await;
-        self.bus.after().and(result)
+        traced!(
+            tracing::debug_span!(
+                "read",
+                address,
+                count = blocks.len(),
+                duration_us = Empty,
+                outcome = Empty
+            ),
+            read(self.bus.get_mut(), self.stats.get_mut(), self.card, address, blocks)
+        )
     }
 
     pub async fn write<'a, B>(&mut self, address: LBA, blocks: B) -> Result<(), Error<E>>
     where
         B: core::iter::ExactSizeIterator<Item = &'a [u8; BLOCK_SIZE]>,
     {
-        if blocks.len() == 0 {
-            return Ok(());
+        if self.locked {
+            return Err(ErrorKind::Locked.into());
         }
-        let address = if self.card.high_capacity() { address } else { address * BLOCK_SIZE as u32 };
-        self.bus.before()?;
-        let result = self.bus.write(address, blocks).await;
-        self.bus.after().and(result)
+        traced!(
+            tracing::debug_span!(
+                "write",
+                address,
+                count = blocks.len(),
+                duration_us = Empty,
+                outcome = Empty
+            ),
+            write(self.bus.get_mut(), self.stats.get_mut(), self.card, address, blocks)
+        )
+    }
+
+    /// Statistics since init or last reset, including commands issued by bus
+    pub fn statistics(&self) -> stats::Statistics {
+        let mut stats = self.stats.borrow_mut();
+        stats.add(self.bus.borrow_mut().take_activity());
+        *stats
+    }
+
+    pub fn reset_statistics(&mut self) {
+        self.bus.get_mut().take_activity();
+        *self.stats.get_mut() = Default::default();
     }
 
     pub fn num_blocks(&self) -> NumBlocks {
@@ -88,3 +249,76 @@ where
         self.csd.block_size_shift()
     }
 }
+
+/// SDSC addressed in bytes
+fn card_address(card: sd::Card, address: LBA) -> u32 {
+    if card.high_capacity() {
+        address
+    } else {
+        address * BLOCK_SIZE as u32
+    }
+}
+
+/// Records address of failed block, following those completed
+fn failed_block<E>(error: Error<E>, address: LBA) -> Error<E> {
+    let address = address + error.completed().unwrap_or(0);
+    error.with_address(address)
+}
+
+#[cfg_attr(not(feature = "async"), deasync::deasync)]
+async fn read<'a, E, BUS, B>(
+    bus: &mut BUS,
+    stats: &mut stats::Statistics,
+    card: sd::Card,
+    address: LBA,
+    blocks: B,
+) -> Result<(), Error<E>>
+where
+    BUS: bus::Read<Error = E> + bus::Bus<Error = E>,
+    B: core::iter::ExactSizeIterator<Item = &'a mut [u8; BLOCK_SIZE]>,
+{
+    if blocks.len() == 0 {
+        return Ok(());
+    }
+    bus.before()?;
+    let (start, count) = (bus.uptime(), blocks.len() as u64);
+    let result = bus.read(card_address(card, address), blocks).await;
+    match &result {
+        Ok(_) => {
+            stats.read.record_since(start, bus.uptime());
+            stats.blocks_read += count;
+        }
+        Err(e) => stats.blocks_read += e.completed().unwrap_or(0) as u64,
+    }
+    stats.count(&result);
+    bus.after().and(result.map_err(|e| failed_block(e, address)))
+}
+
+#[cfg_attr(not(feature = "async"), deasync::deasync)]
+async fn write<'a, E, BUS, B>(
+    bus: &mut BUS,
+    stats: &mut stats::Statistics,
+    card: sd::Card,
+    address: LBA,
+    blocks: B,
+) -> Result<(), Error<E>>
+where
+    BUS: bus::Write<Error = E> + bus::Bus<Error = E>,
+    B: core::iter::ExactSizeIterator<Item = &'a [u8; BLOCK_SIZE]>,
+{
+    if blocks.len() == 0 {
+        return Ok(());
+    }
+    bus.before()?;
+    let (start, count) = (bus.uptime(), blocks.len() as u64);
+    let result = bus.write(card_address(card, address), blocks).await;
+    match &result {
+        Ok(_) => {
+            stats.write.record_since(start, bus.uptime());
+            stats.blocks_written += count;
+        }
+        Err(e) => stats.blocks_written += e.completed().unwrap_or(0) as u64,
+    }
+    stats.count(&result);
+    bus.after().and(result.map_err(|e| failed_block(e, address)))
+}
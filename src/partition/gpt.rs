@@ -0,0 +1,614 @@
+use core::{cmp::min, fmt, iter};
+
+use super::{align_start, mbr, Error, Fit, Partition};
+use crate::{block::Device, sd::BLOCK_SIZE};
+
+const SIGNATURE: &[u8; 8] = b"EFI PART";
+const HEADER_SIZE: usize = 92;
+const ENTRY_SIZE: usize = 128;
+const NAME_LENGTH: usize = 36;
+/// Number of entries in a new table, minimum required by UEFI
+const NUM_ENTRIES: u32 = 128;
+
+/// CRC32 as in zlib, continuing from `crc` so that data may be fed in pieces
+pub(crate) fn crc32(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB88320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+fn put(bytes: &mut [u8], offset: usize, value: &[u8]) {
+    bytes[offset..offset + value.len()].copy_from_slice(value);
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// In mixed-endian on-disk layout
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    pub const UNUSED: Self = Self([0; 16]);
+    pub const EFI_SYSTEM: Self =
+        Self::new(0xC12A7328, 0xF81F, 0x11D2, [0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B]);
+    pub const BASIC_DATA: Self =
+        Self::new(0xEBD0A0A2, 0xB9E5, 0x4433, [0x87, 0xC0, 0x68, 0xB6, 0xB7, 0x26, 0x99, 0xC7]);
+    pub const LINUX_FILESYSTEM: Self =
+        Self::new(0x0FC63DAF, 0x8483, 0x4772, [0x8E, 0x79, 0x3D, 0x69, 0xD8, 0x47, 0x7D, 0xE4]);
+
+    /// From fields as written in `XXXXXXXX-XXXX-XXXX-XXXX-XXXXXXXXXXXX` form
+    pub const fn new(a: u32, b: u16, c: u16, d: [u8; 8]) -> Self {
+        let (a, b, c) = (a.to_le_bytes(), b.to_le_bytes(), c.to_le_bytes());
+        Self([
+            a[0], a[1], a[2], a[3], b[0], b[1], c[0], c[1], d[0], d[1], d[2], d[3], d[4], d[5],
+            d[6], d[7],
+        ])
+    }
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes = &self.0;
+        let a = u32_at(bytes, 0);
+        let (b, c) =
+            (u16::from_le_bytes([bytes[4], bytes[5]]), u16::from_le_bytes([bytes[6], bytes[7]]));
+        write!(f, "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-", a, b, c, bytes[8], bytes[9])?;
+        bytes[10..].iter().try_for_each(|byte| write!(f, "{:02X}", byte))
+    }
+}
+
+impl fmt::Debug for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Guid {
+    fn format(&self, f: defmt::Formatter) {
+        let bytes = &self.0;
+        let a = u32_at(bytes, 0);
+        let (b, c) =
+            (u16::from_le_bytes([bytes[4], bytes[5]]), u16::from_le_bytes([bytes[6], bytes[7]]));
+        defmt::write!(f, "{=u32:08X}-{=u16:04X}-{=u16:04X}-", a, b, c);
+        for (i, byte) in bytes[8..].iter().enumerate() {
+            if i == 2 {
+                defmt::write!(f, "-");
+            }
+            defmt::write!(f, "{=u8:02X}", byte);
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Header {
+    /// LBA of this header
+    pub current: u64,
+    /// LBA of the other copy of header
+    pub alternate: u64,
+    pub first_usable: u64,
+    pub last_usable: u64,
+    pub disk_guid: Guid,
+    pub entries_lba: u64,
+    pub num_entries: u32,
+    pub entry_size: u32,
+    pub entries_crc32: u32,
+}
+
+impl Header {
+    /// Checks signature, size and CRC32 of header
+    fn parse(block: &[u8; BLOCK_SIZE]) -> Option<Self> {
+        let size = u32_at(block, 12) as usize;
+        if &block[..8] != SIGNATURE || !(HEADER_SIZE..=BLOCK_SIZE).contains(&size) {
+            return None;
+        }
+        let mut bytes = [0u8; BLOCK_SIZE];
+        bytes[..size].copy_from_slice(&block[..size]);
+        bytes[16..20].fill(0);
+        if crc32(0, &bytes[..size]) != u32_at(block, 16) {
+            return None;
+        }
+        let header = Self {
+            current: u64_at(block, 24),
+            alternate: u64_at(block, 32),
+            first_usable: u64_at(block, 40),
+            last_usable: u64_at(block, 48),
+            disk_guid: Guid(block[56..72].try_into().unwrap()),
+            entries_lba: u64_at(block, 72),
+            num_entries: u32_at(block, 80),
+            entry_size: u32_at(block, 84),
+            entries_crc32: u32_at(block, 88),
+        };
+        // Entry size of 128 × 2^n never lets an entry straddle blocks
+        let entry_size = header.entry_size as usize;
+        match entry_size.is_power_of_two() && (ENTRY_SIZE..=BLOCK_SIZE).contains(&entry_size) {
+            true => Some(header),
+            false => None,
+        }
+    }
+
+    fn to_bytes(self, block: &mut [u8; BLOCK_SIZE]) {
+        block.fill(0);
+        put(block, 0, SIGNATURE);
+        put(block, 8, &0x10000u32.to_le_bytes()); // Revision 1.0
+        put(block, 12, &(HEADER_SIZE as u32).to_le_bytes());
+        put(block, 24, &self.current.to_le_bytes());
+        put(block, 32, &self.alternate.to_le_bytes());
+        put(block, 40, &self.first_usable.to_le_bytes());
+        put(block, 48, &self.last_usable.to_le_bytes());
+        put(block, 56, &self.disk_guid.0);
+        put(block, 72, &self.entries_lba.to_le_bytes());
+        put(block, 80, &self.num_entries.to_le_bytes());
+        put(block, 84, &self.entry_size.to_le_bytes());
+        put(block, 88, &self.entries_crc32.to_le_bytes());
+        let crc = crc32(0, &block[..HEADER_SIZE]);
+        put(block, 16, &crc.to_le_bytes());
+    }
+
+    fn entries_blocks(&self) -> u64 {
+        (self.num_entries as u64 * self.entry_size as u64).div_ceil(BLOCK_SIZE as u64)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Entry {
+    pub kind: Guid,
+    pub guid: Guid,
+    pub start: u64,
+    /// Inclusive
+    pub end: u64,
+    pub attributes: u64,
+    /// UTF-16 padded with zeros
+    pub name: [u16; NAME_LENGTH],
+}
+
+impl Default for Entry {
+    fn default() -> Self {
+        let (kind, guid) = (Guid::UNUSED, Guid::UNUSED);
+        Self { kind, guid, start: 0, end: 0, attributes: 0, name: [0; NAME_LENGTH] }
+    }
+}
+
+impl Entry {
+    fn from_bytes(bytes: &[u8]) -> Self {
+        let name =
+            core::array::from_fn(|i| u16::from_le_bytes([bytes[56 + i * 2], bytes[57 + i * 2]]));
+        Self {
+            kind: Guid(bytes[..16].try_into().unwrap()),
+            guid: Guid(bytes[16..32].try_into().unwrap()),
+            start: u64_at(bytes, 32),
+            end: u64_at(bytes, 40),
+            attributes: u64_at(bytes, 48),
+            name,
+        }
+    }
+
+    fn to_bytes(self, bytes: &mut [u8]) {
+        put(bytes, 0, &self.kind.0);
+        put(bytes, 16, &self.guid.0);
+        put(bytes, 32, &self.start.to_le_bytes());
+        put(bytes, 40, &self.end.to_le_bytes());
+        put(bytes, 48, &self.attributes.to_le_bytes());
+        for (i, c) in self.name.iter().enumerate() {
+            put(bytes, 56 + i * 2, &c.to_le_bytes());
+        }
+    }
+
+    pub fn num_blocks(&self) -> u64 {
+        (self.end + 1).saturating_sub(self.start)
+    }
+
+    /// Blocks requested when creating, zero `end` asking for as many as available
+    fn requested(&self) -> u64 {
+        if self.end == 0 {
+            0
+        } else {
+            self.num_blocks()
+        }
+    }
+
+    /// Truncated to 36 UTF-16 code units
+    pub fn set_name(&mut self, name: &str) {
+        self.name = [0; NAME_LENGTH];
+        self.name.iter_mut().zip(name.encode_utf16()).for_each(|(c, unit)| *c = unit);
+    }
+
+    /// Name decoded, with invalid UTF-16 replaced by U+FFFD
+    pub fn name(&self) -> impl Iterator<Item = char> + '_ {
+        let name = self.name.iter().copied().take_while(|&c| c != 0);
+        char::decode_utf16(name).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+    }
+
+    /// Partition beyond 32-bit LBA ends up empty, as `Device` addresses blocks in `u32`
+    pub fn partition<D: Device>(&self, device: D) -> Partition<D> {
+        let start = u32::try_from(self.start).unwrap_or(u32::MAX);
+        let num_blocks = u32::try_from(self.num_blocks()).unwrap_or(u32::MAX);
+        Partition::new(device, start, num_blocks)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Table {
+    /// Header in use, primary one unless corrupted
+    pub header: Header,
+    /// Number of partitions read
+    pub count: usize,
+    pub primary_valid: bool,
+    pub backup_valid: bool,
+}
+
+enum Check {
+    Missing,
+    Corrupted,
+    Valid(Header, usize),
+}
+
+/// Reads header at `address` and its entry array, storing used entries into `partitions`
+#[cfg_attr(not(feature = "async"), deasync::deasync)]
+async fn check<D: Device>(
+    device: &mut D,
+    address: u32,
+    partitions: &mut [Entry],
+) -> Result<Check, D::Error> {
+    let mut block = [0u8; BLOCK_SIZE];
+    device.read(address, iter::once(&mut block)).await?;
+    if &block[..8] != SIGNATURE {
+        return Ok(Check::Missing);
+    }
+    let header = match Header::parse(&block) {
+        Some(header) if header.current == address as u64 => header,
+        _ => return Ok(Check::Corrupted),
+    };
+    let entry_size = header.entry_size as usize;
+    let size = header.num_entries as u64 * entry_size as u64;
+    let num_blocks = size.div_ceil(BLOCK_SIZE as u64);
+    if header.entries_lba.saturating_add(num_blocks) > device.num_blocks() as u64 {
+        return Ok(Check::Corrupted);
+    }
+    let (mut crc, mut count) = (0, 0);
+    for index in 0..num_blocks {
+        let address = (header.entries_lba + index) as u32;
+        device.read(address, iter::once(&mut block)).await?;
+        let size = min(size - index * BLOCK_SIZE as u64, BLOCK_SIZE as u64) as usize;
+        crc = crc32(crc, &block[..size]);
+        for entry in block[..size].chunks(entry_size).map(Entry::from_bytes) {
+            if entry.kind != Guid::UNUSED && count < partitions.len() {
+                partitions[count] = entry;
+                count += 1;
+            }
+        }
+    }
+    match crc == header.entries_crc32 {
+        true => Ok(Check::Valid(header, count)),
+        false => Ok(Check::Corrupted),
+    }
+}
+
+/// Reads used partition entries into `partitions` until full, validating both headers
+/// and falling back to backup one when primary one is corrupted
+#[cfg_attr(not(feature = "async"), deasync::deasync)]
+pub async fn read<D: Device>(
+    device: &mut D,
+    partitions: &mut [Entry],
+) -> Result<Table, Error<D::Error>> {
+    let last = device.num_blocks().saturating_sub(1);
+    let primary = check(device, 1, partitions).await?;
+    let backup = match primary {
+        Check::Valid(header, _) => {
+            let address = u32::try_from(header.alternate).unwrap_or(last).min(last);
+            check(device, address, &mut []).await?
+        }
+        _ => check(device, last, partitions).await?,
+    };
+    let backup_valid = matches!(backup, Check::Valid(..));
+    match (primary, backup) {
+        (Check::Valid(header, count), _) => {
+            Ok(Table { header, count, primary_valid: true, backup_valid })
+        }
+        (_, Check::Valid(header, count)) => {
+            Ok(Table { header, count, primary_valid: false, backup_valid })
+        }
+        (Check::Missing, Check::Missing) => Err(Error::NoPartitionTable),
+        _ => Err(Error::Corrupted),
+    }
+}
+
+/// First usable LBA and end of usable area, with backup copy at end of device
+fn usable<E>(num_blocks: u32, entries_blocks: u64) -> Result<(u64, u64), Error<E>> {
+    let (first, end) = (2 + entries_blocks, (num_blocks as u64).saturating_sub(1 + entries_blocks));
+    if first >= end {
+        return Err(Error::InvalidLayout);
+    }
+    Ok((first, end))
+}
+
+#[cfg_attr(not(feature = "async"), deasync::deasync)]
+async fn scan<D, F>(device: &mut D, header: &Header, mut f: F) -> Result<(), D::Error>
+where
+    D: Device,
+    F: FnMut(usize, Entry),
+{
+    let mut block = [0u8; BLOCK_SIZE];
+    let entry_size = header.entry_size as usize;
+    for index in 0..header.entries_blocks() {
+        device.read((header.entries_lba + index) as u32, iter::once(&mut block)).await?;
+        let first = index as usize * (BLOCK_SIZE / entry_size);
+        let entries = block.chunks(entry_size).map(Entry::from_bytes).enumerate();
+        let count = (header.num_entries as usize).saturating_sub(first);
+        entries.take(count).for_each(|(i, entry)| f(first + i, entry));
+    }
+    Ok(())
+}
+
+/// Writes primary copy followed by backup copy at end of device, with entry array
+/// from `source` header or empty, passing each entry through `edit`.
+/// Interrupted at any point, at least one copy stays valid.
+#[cfg_attr(not(feature = "async"), deasync::deasync)]
+async fn commit<D, F>(
+    device: &mut D,
+    source: Option<Header>,
+    disk_guid: Guid,
+    mut edit: F,
+) -> Result<(), Error<D::Error>>
+where
+    D: Device,
+    F: FnMut(usize, &mut Entry),
+{
+    let (num_entries, entry_size) = match source {
+        Some(header) => (header.num_entries, header.entry_size),
+        None => (NUM_ENTRIES, ENTRY_SIZE as u32),
+    };
+    let last = device.num_blocks().saturating_sub(1) as u64;
+    let mut header =
+        Header { disk_guid, entries_lba: 2, num_entries, entry_size, ..Default::default() };
+    let entries_blocks = header.entries_blocks();
+    let (first_usable, end) = usable(device.num_blocks(), entries_blocks)?;
+    header = Header { current: 1, alternate: last, first_usable, last_usable: end - 1, ..header };
+
+    let mut block = [0u8; BLOCK_SIZE];
+    let (size, entry_size) = (num_entries as u64 * entry_size as u64, entry_size as usize);
+    let mut crc = 0;
+    for index in 0..entries_blocks {
+        match source {
+            Some(source) => {
+                device.read((source.entries_lba + index) as u32, iter::once(&mut block)).await?
+            }
+            None => block.fill(0),
+        }
+        let size = min(size - index * BLOCK_SIZE as u64, BLOCK_SIZE as u64) as usize;
+        let first = index as usize * (BLOCK_SIZE / entry_size);
+        for (i, bytes) in block[..size].chunks_mut(entry_size).enumerate() {
+            let mut entry = Entry::from_bytes(bytes);
+            edit(first + i, &mut entry);
+            entry.to_bytes(bytes);
+        }
+        crc = crc32(crc, &block[..size]);
+        device.write((2 + index) as u32, iter::once(&block)).await?;
+    }
+    header.entries_crc32 = crc;
+    header.to_bytes(&mut block);
+    device.write(1, iter::once(&block)).await?;
+
+    for index in 0..entries_blocks {
+        device.read((2 + index) as u32, iter::once(&mut block)).await?;
+        device.write((end + index) as u32, iter::once(&block)).await?;
+    }
+    let backup = Header { current: last, alternate: 1, entries_lba: end, ..header };
+    backup.to_bytes(&mut block);
+    Ok(device.write(last as u32, iter::once(&block)).await?)
+}
+
+/// Writes a fresh GPT with protective MBR, backup copy placed at end of device.
+///
+/// Partitions are laid out as in [`mbr::create`], with `end` + 1 - `start` of given entry
+/// as requested number of blocks and zero `end` extending to end of usable area.
+/// GUIDs are taken as given since no source of randomness is assumed.
+/// `partitions` are updated with resulting layout.
+#[cfg_attr(not(feature = "async"), deasync::deasync)]
+pub async fn create<D>(
+    device: &mut D,
+    disk_guid: Guid,
+    partitions: &mut [Entry],
+    alignment: u32,
+) -> Result<(), Error<D::Error>>
+where
+    D: Device,
+{
+    if partitions.len() > NUM_ENTRIES as usize {
+        return Err(Error::InvalidLayout);
+    }
+    let entries_blocks = (NUM_ENTRIES as u64 * ENTRY_SIZE as u64).div_ceil(BLOCK_SIZE as u64);
+    let (first, end) = usable(device.num_blocks(), entries_blocks)?;
+    let alignment = alignment.max(1) as u64;
+    for index in 0..partitions.len() {
+        let (placed, partition) = partitions.split_at_mut(index);
+        let last = placed.iter().map(|p| p.end + 1).max().unwrap_or(first);
+        let start = align_start(partition[0].start, last, alignment);
+        let mut fit = Fit::new(start, first, end);
+        placed.iter().for_each(|p| fit.exclude(p.start, p.num_blocks()));
+        let num_blocks =
+            fit.num_blocks(partition[0].requested(), alignment).ok_or(Error::InvalidLayout)?;
+        partition[0].start = start;
+        partition[0].end = start + num_blocks - 1;
+    }
+    let num_blocks = device.num_blocks().saturating_sub(1);
+    let protective = mbr::Entry { kind: 0xEE, start: 1, num_blocks, ..Default::default() };
+    mbr::create(device, &mut [protective], 1).await?;
+    commit(device, None, disk_guid, |index, entry| {
+        if let Some(partition) = partitions.get(index) {
+            *entry = *partition;
+        }
+    })
+    .await
+}
+
+/// Adds a partition into first free entry, placed as in [`create`]
+/// without moving existing partitions
+#[cfg_attr(not(feature = "async"), deasync::deasync)]
+pub async fn add<D: Device>(
+    device: &mut D,
+    partition: Entry,
+    alignment: u32,
+) -> Result<Entry, Error<D::Error>> {
+    let header = read(device, &mut []).await?.header;
+    let (first, end) = usable(device.num_blocks(), header.entries_blocks())?;
+    let (mut free, mut last) = (None, first);
+    scan(device, &header, |index, entry| match entry.kind {
+        Guid::UNUSED => free = free.or(Some(index)),
+        _ => last = last.max(entry.end + 1),
+    })
+    .await?;
+    let index = free.ok_or(Error::InvalidLayout)?;
+    let alignment = alignment.max(1) as u64;
+    let start = align_start(partition.start, last, alignment);
+    let mut fit = Fit::new(start, first, end);
+    scan(device, &header, |_, entry| {
+        if entry.kind != Guid::UNUSED {
+            fit.exclude(entry.start, entry.num_blocks())
+        }
+    })
+    .await?;
+    let num_blocks =
+        fit.num_blocks(partition.requested(), alignment).ok_or(Error::InvalidLayout)?;
+    let partition = Entry { start, end: start + num_blocks - 1, ..partition };
+    commit(device, Some(header), header.disk_guid, |i, entry| {
+        if i == index {
+            *entry = partition;
+        }
+    })
+    .await?;
+    Ok(partition)
+}
+
+/// Resizes partition with unique `guid` without moving its start,
+/// zero `num_blocks` extends it up to next partition or end of usable area
+#[cfg_attr(not(feature = "async"), deasync::deasync)]
+pub async fn resize<D>(
+    device: &mut D,
+    guid: Guid,
+    num_blocks: u64,
+    alignment: u32,
+) -> Result<Entry, Error<D::Error>>
+where
+    D: Device,
+{
+    let header = read(device, &mut []).await?.header;
+    let (first, end) = usable(device.num_blocks(), header.entries_blocks())?;
+    let mut found = None;
+    scan(device, &header, |index, entry| {
+        if entry.kind != Guid::UNUSED && entry.guid == guid {
+            found = found.or(Some((index, entry)));
+        }
+    })
+    .await?;
+    let (index, partition) = found.ok_or(Error::InvalidLayout)?;
+    let mut fit = Fit::new(partition.start, first, end);
+    scan(device, &header, |i, entry| {
+        if i != index && entry.kind != Guid::UNUSED {
+            fit.exclude(entry.start, entry.num_blocks())
+        }
+    })
+    .await?;
+    let num_blocks =
+        fit.num_blocks(num_blocks, alignment.max(1) as u64).ok_or(Error::InvalidLayout)?;
+    let partition = Entry { end: partition.start + num_blocks - 1, ..partition };
+    commit(device, Some(header), header.disk_guid, |i, entry| {
+        if i == index {
+            *entry = partition;
+        }
+    })
+    .await?;
+    Ok(partition)
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_read_with_fallback() {
+        use super::{crc32, read, Entry, Guid};
+        use crate::block::test::{block_on, Memory};
+
+        assert_eq!(crc32(0, b"123456789"), 0xCBF43926);
+
+        fn header(block: &mut [u8; 512], current: u64, alternate: u64, entries_lba: u64, crc: u32) {
+            block[..8].copy_from_slice(b"EFI PART");
+            block[8..12].copy_from_slice(&0x10000u32.to_le_bytes());
+            block[12..16].copy_from_slice(&92u32.to_le_bytes());
+            block[24..32].copy_from_slice(&current.to_le_bytes());
+            block[32..40].copy_from_slice(&alternate.to_le_bytes());
+            block[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+            block[80..84].copy_from_slice(&4u32.to_le_bytes());
+            block[84..88].copy_from_slice(&128u32.to_le_bytes());
+            block[88..92].copy_from_slice(&crc.to_le_bytes());
+            let crc = crc32(0, &block[..92]);
+            block[16..20].copy_from_slice(&crc.to_le_bytes());
+        }
+
+        let mut memory = Memory::<8>::default();
+        let entries = &mut memory.blocks[2];
+        entries[..16].copy_from_slice(&Guid::LINUX_FILESYSTEM.0);
+        entries[32..40].copy_from_slice(&3u64.to_le_bytes());
+        entries[40..48].copy_from_slice(&5u64.to_le_bytes());
+        entries[56..62].copy_from_slice(&[b'r', 0, b'o', 0, b'o', 0]);
+        entries[62..64].copy_from_slice(&[b't', 0]);
+        memory.blocks[6] = memory.blocks[2];
+        let crc = crc32(0, &memory.blocks[2]);
+        header(&mut memory.blocks[1], 1, 7, 2, crc);
+        header(&mut memory.blocks[7], 7, 1, 6, crc);
+
+        let mut partitions = [Entry::default(); 2];
+        let table = block_on(read(&mut memory, &mut partitions)).unwrap();
+        assert_eq!((table.count, table.primary_valid, table.backup_valid), (1, true, true));
+        assert_eq!((partitions[0].kind, partitions[0].num_blocks()), (Guid::LINUX_FILESYSTEM, 3));
+        assert!(partitions[0].name().eq("root".chars()));
+        assert_eq!(format!("{}", Guid::BASIC_DATA), "EBD0A0A2-B9E5-4433-87C0-68B6B72699C7");
+
+        memory.blocks[2][40] = 7;
+        let mut partitions = [Entry::default(); 2];
+        let table = block_on(read(&mut memory, &mut partitions)).unwrap();
+        assert_eq!((table.count, table.primary_valid, table.header.current), (1, false, 7));
+        assert_eq!(partitions[0].end, 5);
+    }
+
+    #[test]
+    fn test_create_and_edit() {
+        use super::{add, create, read, resize, Entry, Guid};
+        use crate::block::test::{block_on, Memory};
+
+        let mut memory = Memory::<128>::default();
+        let mut partition = Entry { kind: Guid::EFI_SYSTEM, end: 15, ..Default::default() };
+        partition.guid.0[0] = 1;
+        partition.set_name("boot");
+        let mut partitions = [partition];
+        block_on(create(&mut memory, Guid([0xAA; 16]), &mut partitions, 8)).unwrap();
+        assert_eq!((partitions[0].start, partitions[0].end), (40, 55));
+        assert_eq!(memory.blocks[0][450], 0xEE);
+
+        let mut partition = Entry { kind: Guid::BASIC_DATA, ..Default::default() };
+        partition.guid.0[0] = 2;
+        let partition = block_on(add(&mut memory, partition, 8)).unwrap();
+        assert_eq!((partition.start, partition.end), (56, 87));
+        let partition = block_on(resize(&mut memory, partition.guid, 1, 8)).unwrap();
+        assert_eq!(partition.end, 63);
+
+        let mut partitions = [Entry::default(); 4];
+        let table = block_on(read(&mut memory, &mut partitions)).unwrap();
+        assert_eq!((table.count, table.primary_valid, table.backup_valid), (2, true, true));
+        assert_eq!((table.header.disk_guid, table.header.alternate), (Guid([0xAA; 16]), 127));
+        assert!(partitions[0].name().eq("boot".chars()));
+        assert_eq!((partitions[1].start, partitions[1].end), (56, 63));
+    }
+}
@@ -0,0 +1,289 @@
+use core::iter;
+
+use super::{align_start, Error, Fit, Partition};
+use crate::{block::Device, sd::BLOCK_SIZE};
+
+const TABLE_OFFSET: usize = 446;
+const ENTRY_SIZE: usize = 16;
+const SIGNATURE: [u8; 2] = [0x55, 0xAA];
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Entry {
+    pub bootable: bool,
+    /// Partition type, e.g. 0x0C for FAT32 with LBA
+    pub kind: u8,
+    /// Absolute LBA of first block
+    pub start: u32,
+    pub num_blocks: u32,
+}
+
+impl Entry {
+    fn from_bytes(bytes: &[u8]) -> Self {
+        let word =
+            |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        Self { bootable: bytes[0] == 0x80, kind: bytes[4], start: word(8), num_blocks: word(12) }
+    }
+
+    fn to_bytes(self, bytes: &mut [u8]) {
+        // CHS addresses left as maximum, so that LBA fields are used
+        let chs = match self.kind {
+            0 => [0; 3],
+            _ => [0xFE, 0xFF, 0xFF],
+        };
+        bytes[0] = if self.bootable { 0x80 } else { 0 };
+        bytes[1..4].copy_from_slice(&chs);
+        bytes[4] = self.kind;
+        bytes[5..8].copy_from_slice(&chs);
+        bytes[8..12].copy_from_slice(&self.start.to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.num_blocks.to_le_bytes());
+    }
+
+    fn is_extended(&self) -> bool {
+        matches!(self.kind, 0x05 | 0x0F | 0x85)
+    }
+
+    pub fn partition<D: Device>(&self, device: D) -> Partition<D> {
+        Partition::new(device, self.start, self.num_blocks)
+    }
+}
+
+fn entries(block: &[u8; BLOCK_SIZE]) -> Option<impl Iterator<Item = Entry> + '_> {
+    if block[BLOCK_SIZE - 2..] != SIGNATURE {
+        return None;
+    }
+    let table = &block[TABLE_OFFSET..TABLE_OFFSET + 4 * ENTRY_SIZE];
+    Some(table.chunks(ENTRY_SIZE).map(Entry::from_bytes))
+}
+
+fn set_entries(block: &mut [u8; BLOCK_SIZE], entries: &[Entry; 4]) {
+    let table = &mut block[TABLE_OFFSET..TABLE_OFFSET + 4 * ENTRY_SIZE];
+    table.chunks_mut(ENTRY_SIZE).zip(entries).for_each(|(bytes, entry)| entry.to_bytes(bytes));
+    block[BLOCK_SIZE - 2..].copy_from_slice(&SIGNATURE);
+}
+
+/// Fits `entry` among `entries` except the one at `index`
+fn fit_among(
+    entries: &[Entry; 4],
+    index: usize,
+    entry: Entry,
+    end: u32,
+    alignment: u32,
+) -> Option<u32> {
+    let mut fit = Fit::new(entry.start as u64, 1, end as u64);
+    for (_, other) in entries.iter().enumerate().filter(|&(i, e)| i != index && e.kind != 0) {
+        fit.exclude(other.start as u64, other.num_blocks as u64);
+    }
+    fit.num_blocks(entry.num_blocks as u64, alignment.max(1) as u64).map(|n| n as u32)
+}
+
+#[cfg_attr(not(feature = "async"), deasync::deasync)]
+async fn edit<D, F>(device: &mut D, f: F) -> Result<Entry, Error<D::Error>>
+where
+    D: Device,
+    F: FnOnce(&mut [Entry; 4], u32) -> Option<usize>,
+{
+    let mut block = [0u8; BLOCK_SIZE];
+    device.read(0, iter::once(&mut block)).await?;
+    let mut table = entries(&block).ok_or(Error::NoPartitionTable)?;
+    let mut entries: [Entry; 4] = core::array::from_fn(|_| table.next().unwrap_or_default());
+    drop(table);
+    let index = f(&mut entries, device.num_blocks()).ok_or(Error::InvalidLayout)?;
+    set_entries(&mut block, &entries);
+    device.write(0, iter::once(&block)).await?;
+    Ok(entries[index])
+}
+
+/// Writes a fresh MBR with up to 4 primary partitions, keeping existing boot code.
+///
+/// Partitions are laid out in order on `alignment` boundaries, usually
+/// [`SD::allocation_unit`](crate::SD::allocation_unit): zero `start` places a partition
+/// right after the previous one and zero `num_blocks` extends it to end of device.
+/// `partitions` are updated with resulting layout.
+#[cfg_attr(not(feature = "async"), deasync::deasync)]
+pub async fn create<D>(
+    device: &mut D,
+    partitions: &mut [Entry],
+    alignment: u32,
+) -> Result<(), Error<D::Error>>
+where
+    D: Device,
+{
+    if partitions.len() > 4 {
+        return Err(Error::InvalidLayout);
+    }
+    let mut entries = [Entry::default(); 4];
+    let (alignment, end) = (alignment.max(1), device.num_blocks());
+    for (index, partition) in partitions.iter_mut().enumerate() {
+        let last = entries[..index].iter().map(|e| e.start + e.num_blocks).max().unwrap_or(1);
+        partition.start = align_start(partition.start as u64, last as u64, alignment as u64) as u32;
+        partition.num_blocks =
+            fit_among(&entries, index, *partition, end, alignment).ok_or(Error::InvalidLayout)?;
+        entries[index] = *partition;
+    }
+    let mut block = [0u8; BLOCK_SIZE];
+    device.read(0, iter::once(&mut block)).await?;
+    if block[BLOCK_SIZE - 2..] != SIGNATURE {
+        block.fill(0);
+    }
+    set_entries(&mut block, &entries);
+    Ok(device.write(0, iter::once(&block)).await?)
+}
+
+/// Adds a primary partition into first free entry, placed as in [`create`]
+/// without moving existing partitions
+#[cfg_attr(not(feature = "async"), deasync::deasync)]
+pub async fn add<D: Device>(
+    device: &mut D,
+    partition: Entry,
+    alignment: u32,
+) -> Result<Entry, Error<D::Error>> {
+    edit(device, |entries, end| {
+        let index = entries.iter().position(|entry| entry.kind == 0)?;
+        let last = entries.iter().filter(|e| e.kind != 0).map(|e| e.start + e.num_blocks).max();
+        let start =
+            align_start(partition.start as u64, last.unwrap_or(1) as u64, alignment.max(1) as u64);
+        let entry = Entry { start: u32::try_from(start).ok()?, ..partition };
+        entries[index] =
+            Entry { num_blocks: fit_among(entries, index, entry, end, alignment)?, ..entry };
+        Some(index)
+    })
+    .await
+}
+
+/// Resizes primary partition at `index` of partition table without moving its start,
+/// zero `num_blocks` extends it up to next partition or end of device
+#[cfg_attr(not(feature = "async"), deasync::deasync)]
+pub async fn resize<D>(
+    device: &mut D,
+    index: usize,
+    num_blocks: u32,
+    alignment: u32,
+) -> Result<Entry, Error<D::Error>>
+where
+    D: Device,
+{
+    edit(device, |entries, end| {
+        let entry = Entry { num_blocks, ..*entries.get(index).filter(|e| e.kind != 0)? };
+        entries[index].num_blocks = fit_among(entries, index, entry, end, alignment)?;
+        Some(index)
+    })
+    .await
+}
+
+/// Reads primary partitions followed by logical partitions within extended partition,
+/// into `partitions` until full, returns number of partitions read
+#[cfg_attr(not(feature = "async"), deasync::deasync)]
+pub async fn read<D: Device>(
+    device: &mut D,
+    partitions: &mut [Entry],
+) -> Result<usize, Error<D::Error>> {
+    let mut block = [0u8; BLOCK_SIZE];
+    device.read(0, iter::once(&mut block)).await?;
+    let mut count = 0;
+    let mut extended = None;
+    for entry in entries(&block).ok_or(Error::NoPartitionTable)? {
+        match entry {
+            Entry { kind: 0, .. } => (),
+            entry if entry.is_extended() => extended = extended.or(Some(entry.start)),
+            entry if count < partitions.len() => {
+                partitions[count] = entry;
+                count += 1;
+            }
+            _ => (),
+        }
+    }
+    let Some(base) = extended else { return Ok(count) };
+    // Each EBR describes one logical partition relative to itself,
+    // and links to next EBR relative to start of extended partition
+    let mut address = base;
+    while count < partitions.len() {
+        device.read(address, iter::once(&mut block)).await?;
+        // MBR already valid, so a broken link is a damaged chain
+        let mut table = entries(&block).ok_or(Error::Corrupted)?;
+        let (logical, next) = (table.next().unwrap_or_default(), table.next().unwrap_or_default());
+        if logical.kind != 0 {
+            partitions[count] = Entry { start: address + logical.start, ..logical };
+            count += 1;
+        }
+        // Links only going forward, so that a looping chain terminates
+        match base.checked_add(next.start) {
+            Some(next_address) if next.is_extended() && next_address > address => {
+                address = next_address
+            }
+            _ => break,
+        }
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_read_partitions() {
+        use super::{read, Entry};
+        use crate::block::{
+            test::{block_on, Memory},
+            Device,
+        };
+        use crate::partition::Error;
+
+        fn set(block: &mut [u8; 512], index: usize, kind: u8, start: u32, num_blocks: u32) {
+            let entry = &mut block[446 + index * 16..][..16];
+            entry[4] = kind;
+            entry[8..12].copy_from_slice(&start.to_le_bytes());
+            entry[12..16].copy_from_slice(&num_blocks.to_le_bytes());
+            block[510..].copy_from_slice(&[0x55, 0xAA]);
+        }
+
+        let mut memory = Memory::<32>::default();
+        set(&mut memory.blocks[0], 0, 0x0C, 1, 7);
+        set(&mut memory.blocks[0], 1, 0x05, 8, 24);
+        set(&mut memory.blocks[8], 0, 0x83, 1, 7);
+        set(&mut memory.blocks[8], 1, 0x05, 8, 16);
+        set(&mut memory.blocks[16], 0, 0x83, 2, 14);
+
+        let mut partitions = [Entry::default(); 4];
+        let count = block_on(read(&mut memory, &mut partitions)).unwrap();
+        let ranges: [_; 3] =
+            core::array::from_fn(|i| (partitions[i].start, partitions[i].num_blocks));
+        assert_eq!((count, ranges), (3, [(1, 7), (9, 7), (18, 14)]));
+
+        let mut partition = partitions[1].partition(&mut memory);
+        let block = [0xAAu8; 512];
+        block_on(partition.write(6, core::iter::once(&block))).unwrap();
+        let blocks = [block; 2];
+        let result = block_on(partition.write(6, blocks.iter()));
+        assert!(matches!(result, Err(Error::OutOfRange)));
+        assert_eq!(memory.blocks[15], block);
+        assert_eq!(memory.blocks[16][..2], [0, 0]);
+
+        memory.blocks[16][510] = 0;
+        let result = block_on(read(&mut memory, &mut partitions));
+        assert!(matches!(result, Err(Error::Corrupted)));
+    }
+
+    #[test]
+    fn test_create_and_edit() {
+        use super::{add, create, read, resize, Entry};
+        use crate::block::test::{block_on, Memory};
+        use crate::partition::Error;
+
+        let mut memory = Memory::<64>::default();
+        let mut partitions = [Entry { kind: 0x0C, num_blocks: 20, ..Default::default() }];
+        block_on(create(&mut memory, &mut partitions, 8)).unwrap();
+        assert_eq!((partitions[0].start, partitions[0].num_blocks), (8, 24));
+
+        let entry = Entry { kind: 0x83, ..Default::default() };
+        let entry = block_on(add(&mut memory, entry, 8)).unwrap();
+        assert_eq!((entry.start, entry.num_blocks), (32, 32));
+        let result = block_on(resize(&mut memory, 0, 32, 8));
+        assert!(matches!(result, Err(Error::InvalidLayout)));
+        let entry = block_on(resize(&mut memory, 1, 9, 8)).unwrap();
+        assert_eq!(entry.num_blocks, 16);
+
+        let mut partitions = [Entry::default(); 4];
+        assert_eq!(block_on(read(&mut memory, &mut partitions)).unwrap(), 2);
+        assert_eq!((partitions[1].kind, partitions[1].num_blocks), (0x83, 16));
+    }
+}
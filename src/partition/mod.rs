@@ -0,0 +1,130 @@
+pub mod gpt;
+pub mod mbr;
+
+use derive_more::Display;
+use thiserror::Error;
+
+use crate::{block::Device, sd::BLOCK_SIZE};
+
+#[derive(Debug, Error, Display)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<E> {
+    #[display("{_0}")]
+    Device(E),
+    /// Access beyond end of partition
+    #[display("out of range")]
+    OutOfRange,
+    #[display("no partition table")]
+    NoPartitionTable,
+    /// Partition table present but failed validation
+    #[display("corrupted partition table")]
+    Corrupted,
+    /// Partition overlapping another one, not fitting into device after alignment,
+    /// not found, or no free entry left in partition table
+    #[display("invalid layout")]
+    InvalidLayout,
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(error: E) -> Self {
+        Self::Device(error)
+    }
+}
+
+/// Fits a new or resized partition among existing ones, which are fed through `exclude`
+struct Fit {
+    start: u64,
+    /// Start of next partition or end of usable area
+    limit: u64,
+    overlapped: bool,
+}
+
+impl Fit {
+    /// `start` and `end` of usable area, the latter exclusive
+    fn new(start: u64, first: u64, end: u64) -> Self {
+        Self { start, limit: end, overlapped: start < first || start >= end }
+    }
+
+    fn exclude(&mut self, start: u64, num_blocks: u64) {
+        match start <= self.start {
+            true => self.overlapped |= self.start < start + num_blocks,
+            false => self.limit = self.limit.min(start),
+        }
+    }
+
+    /// Zero `num_blocks` takes all available blocks rounded down to `alignment`,
+    /// otherwise rounded up to `alignment`
+    fn num_blocks(&self, num_blocks: u64, alignment: u64) -> Option<u64> {
+        let available = self.limit.saturating_sub(self.start);
+        let num_blocks = match num_blocks {
+            0 => available / alignment * alignment,
+            _ => num_blocks.next_multiple_of(alignment),
+        };
+        (!self.overlapped && num_blocks > 0 && num_blocks <= available).then_some(num_blocks)
+    }
+}
+
+/// Start for a new partition, zero `start` meaning right after `end` of existing ones,
+/// rounded up to `alignment`
+fn align_start(start: u64, end: u64, alignment: u64) -> u64 {
+    match start {
+        0 => end.next_multiple_of(alignment),
+        start => start.next_multiple_of(alignment),
+    }
+}
+
+/// Block device view of a partition, addresses are relative to start of partition
+/// and accesses crossing its end are rejected
+pub struct Partition<D> {
+    device: D,
+    start: u32,
+    num_blocks: u32,
+}
+
+impl<D: Device> Partition<D> {
+    /// `num_blocks` clamped to size of device
+    pub fn new(device: D, start: u32, num_blocks: u32) -> Self {
+        let num_blocks = num_blocks.min(device.num_blocks().saturating_sub(start));
+        Self { device, start, num_blocks }
+    }
+
+    pub fn start(&self) -> u32 {
+        self.start
+    }
+
+    pub fn into_inner(self) -> D {
+        self.device
+    }
+
+    fn translate(&self, address: u32, count: usize) -> Result<u32, Error<D::Error>> {
+        match address.checked_add(count as u32) {
+            Some(end) if end <= self.num_blocks => Ok(self.start + address),
+            _ => Err(Error::OutOfRange),
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "async"), deasync::deasync)]
+impl<D: Device> Device for Partition<D> {
+    type Error = Error<D::Error>;
+
+    fn num_blocks(&self) -> u32 {
+        self.num_blocks
+    }
+
+    async fn read<'a, B>(&mut self, address: u32, blocks: B) -> Result<(), Self::Error>
+    where
+        B: core::iter::ExactSizeIterator<Item = &'a mut [u8; BLOCK_SIZE]>,
+    {
+        let address = self.translate(address, blocks.len())?;
+        Ok(self.device.read(address, blocks).await?)
+    }
+
+    async fn write<'a, B>(&mut self, address: u32, blocks: B) -> Result<(), Self::Error>
+    where
+        B: core::iter::ExactSizeIterator<Item = &'a [u8; BLOCK_SIZE]>,
+    {
+        let address = self.translate(address, blocks.len())?;
+        Ok(self.device.write(address, blocks).await?)
+    }
+}
@@ -0,0 +1,165 @@
+use core::cmp::min;
+
+use crate::{
+    block::{Device, Error},
+    sd::BLOCK_SIZE,
+};
+
+/// Iterator with length known in advance, since `Chain` is not `ExactSizeIterator`
+struct Exact<I> {
+    iter: I,
+    remaining: usize,
+}
+
+impl<I: Iterator> Iterator for Exact<I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        let item = self.iter.next()?;
+        self.remaining -= 1;
+        Some(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<I: Iterator> ExactSizeIterator for Exact<I> {}
+
+/// Prefetches blocks following sequential reads into caller provided buffer
+///
+/// A read continuing where the previous one ended is extended by the size of the buffer
+/// within the same multi-block transfer, any other read outside the buffer invalidates it.
+pub struct ReadAhead<'a, D> {
+    device: D,
+    buffer: &'a mut [[u8; BLOCK_SIZE]],
+    start: u32,
+    filled: u32,
+    next: Option<u32>,
+}
+
+impl<'a, D: Device> ReadAhead<'a, D> {
+    pub fn new(device: D, buffer: &'a mut [[u8; BLOCK_SIZE]]) -> Self {
+        Self { device, buffer, start: 0, filled: 0, next: None }
+    }
+
+    pub fn invalidate(&mut self) {
+        (self.filled, self.next) = (0, None);
+    }
+
+    pub fn into_inner(self) -> D {
+        self.device
+    }
+
+    fn prefetched(&self, address: u32) -> bool {
+        address.wrapping_sub(self.start) < self.filled
+    }
+}
+
+#[cfg_attr(not(feature = "async"), deasync::deasync)]
+impl<'a, D: Device> Device for ReadAhead<'a, D> {
+    type Error = Error<D::Error>;
+
+    fn num_blocks(&self) -> u32 {
+        self.device.num_blocks()
+    }
+
+    async fn read<'b, B>(&mut self, address: u32, blocks: B) -> Result<(), Self::Error>
+    where
+        B: core::iter::ExactSizeIterator<Item = &'b mut [u8; BLOCK_SIZE]>,
+    {
+        let mut blocks = blocks;
+        let sequential = self.next == Some(address) || self.prefetched(address);
+        let end = address.checked_add(blocks.len() as u32).ok_or(Error::OutOfRange)?;
+        let mut address = address;
+        self.next = Some(end);
+        while address < end && self.prefetched(address) {
+            if let Some(block) = blocks.next() {
+                block.copy_from_slice(&self.buffer[(address - self.start) as usize]);
+            }
+            address += 1;
+        }
+        if address == end {
+            return Ok(());
+        }
+        self.filled = 0;
+        if !sequential {
+            return Ok(self.device.read(address, blocks).await?);
+        }
+        let prefetch =
+            min(self.buffer.len(), self.device.num_blocks().saturating_sub(end) as usize);
+        let remaining = blocks.len() + prefetch;
+        // Reborrow caller blocks so that they chain with shorter lived buffer
+        let iter = blocks.map(|block| &mut *block).chain(self.buffer[..prefetch].iter_mut());
+        self.device.read(address, Exact { iter, remaining }).await?;
+        (self.start, self.filled) = (end, prefetch as u32);
+        Ok(())
+    }
+
+    async fn write<'b, B>(&mut self, address: u32, blocks: B) -> Result<(), Self::Error>
+    where
+        B: core::iter::ExactSizeIterator<Item = &'b [u8; BLOCK_SIZE]>,
+    {
+        address.checked_add(blocks.len() as u32).ok_or(Error::OutOfRange)?;
+        let (start, filled, buffer) = (self.start, self.filled, &mut *self.buffer);
+        let blocks = blocks.enumerate().map(|(i, block)| {
+            let index = (address + i as u32).wrapping_sub(start);
+            if index < filled {
+                buffer[index as usize].copy_from_slice(block);
+            }
+            block
+        });
+        let result = self.device.write(address, blocks).await;
+        if result.is_err() {
+            self.invalidate();
+        }
+        Ok(result?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_sequential_read() {
+        use super::ReadAhead;
+        use crate::block::{
+            test::{block_on, Memory},
+            Device,
+        };
+
+        let mut memory = Memory::<16>::default();
+        memory.blocks.iter_mut().enumerate().for_each(|(i, block)| block.fill(i as u8));
+        let mut buffer = [[0u8; 512]; 4];
+        let mut readahead = ReadAhead::new(&mut memory, &mut buffer);
+        let mut block = [0u8; 512];
+        for address in 0..8 {
+            block_on(readahead.read(address, core::iter::once(&mut block))).unwrap();
+            assert_eq!(block, [address as u8; 512]);
+        }
+        block_on(readahead.write(7, core::iter::once(&[0xAA; 512]))).unwrap();
+        block_on(readahead.read(7, core::iter::once(&mut block))).unwrap();
+        assert_eq!(block, [0xAA; 512]);
+        block_on(readahead.read(12, core::iter::once(&mut block))).unwrap();
+        assert_eq!(block, [12; 512]);
+        let memory = readahead.into_inner();
+        assert_eq!(memory.reads, 4);
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        use super::ReadAhead;
+        use crate::block::{
+            test::{block_on, Memory},
+            Device, Error,
+        };
+
+        let mut buffer = [[0u8; 512]; 4];
+        let mut readahead = ReadAhead::new(Memory::<16>::default(), &mut buffer);
+        let mut blocks = [[0u8; 512]; 2];
+        let result = block_on(readahead.read(u32::MAX, blocks.iter_mut()));
+        assert!(matches!(result, Err(Error::OutOfRange)));
+        let result = block_on(readahead.write(u32::MAX, blocks.iter()));
+        assert!(matches!(result, Err(Error::OutOfRange)));
+    }
+}
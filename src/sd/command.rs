@@ -3,6 +3,7 @@ use core::mem;
 use super::response;
 
 #[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct SendInterfaceCondition {
     pub pcie_1_2v_suppport: bool, // PCIe 1.2V
     pub pcie_availability: bool,
@@ -16,12 +17,54 @@ impl SendInterfaceCondition {
     }
 }
 
-impl Into<u32> for SendInterfaceCondition {
-    fn into(self) -> u32 {
-        (self.pcie_1_2v_suppport as u32) << 15
-            | (self.pcie_availability as u32) << 14
-            | (self.voltage_supplied as u32) << 8
-            | self.check_pattern as u32
+impl From<SendInterfaceCondition> for u32 {
+    fn from(cond: SendInterfaceCondition) -> u32 {
+        (cond.pcie_1_2v_suppport as u32) << 15
+            | (cond.pcie_availability as u32) << 14
+            | (cond.voltage_supplied as u32) << 8
+            | cond.check_pattern as u32
+    }
+}
+
+/// Data block of LOCK_UNLOCK, passwords up to 16 bytes
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LockUnlock<'a> {
+    /// `old` being empty if no password set yet
+    SetPassword {
+        old: &'a [u8],
+        new: &'a [u8],
+    },
+    ClearPassword(&'a [u8]),
+    Lock(&'a [u8]),
+    Unlock(&'a [u8]),
+    /// Erases all user data and password, unlocking the card
+    ForceErase,
+}
+
+pub const MAX_PASSWORD_LENGTH: usize = 16;
+
+impl LockUnlock<'_> {
+    /// Encodes into `buffer` and returns length, `None` if password too long
+    pub fn encode(&self, buffer: &mut [u8; 2 + 2 * MAX_PASSWORD_LENGTH]) -> Option<usize> {
+        let (flags, old, new): (u8, &[u8], &[u8]) = match *self {
+            Self::SetPassword { old, new } => (0b0001, old, new),
+            Self::ClearPassword(password) => (0b0010, password, &[]),
+            Self::Lock(password) => (0b0100, password, &[]),
+            Self::Unlock(password) => (0b0000, password, &[]),
+            Self::ForceErase => {
+                buffer[0] = 0b1000;
+                return Some(1);
+            }
+        };
+        if old.len() > MAX_PASSWORD_LENGTH || new.len() > MAX_PASSWORD_LENGTH {
+            return None;
+        }
+        let length = old.len() + new.len();
+        buffer[0] = flags;
+        buffer[1] = length as u8;
+        buffer[2..2 + old.len()].copy_from_slice(old);
+        buffer[2 + old.len()..2 + length].copy_from_slice(new);
+        Some(2 + length)
     }
 }
 
@@ -30,9 +73,14 @@ pub type Address = u32;
 
 #[repr(u8)]
 #[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum AppCommand {
     SDSendOpCond(bool), // host-capability-support
     ReadOCR,
+    SDStatus,
+    SendNumWriteBlocks,
+    SetWriteBlockEraseCount(u32),
+    SendSCR,
 }
 
 impl AppCommand {
@@ -40,19 +88,25 @@ impl AppCommand {
         match self {
             Self::SDSendOpCond(_) => 41,
             Self::ReadOCR => 58,
+            Self::SDStatus => 13,
+            Self::SendNumWriteBlocks => 22,
+            Self::SetWriteBlockEraseCount(_) => 23,
+            Self::SendSCR => 51,
         }
     }
 
     pub fn argument(self) -> u32 {
         match self {
             Self::SDSendOpCond(hcs) => (hcs as u32) << 30,
-            Self::ReadOCR => 0,
+            Self::ReadOCR | Self::SDStatus | Self::SendNumWriteBlocks | Self::SendSCR => 0,
+            Self::SetWriteBlockEraseCount(count) => count & 0x7F_FFFF,
         }
     }
 
     pub fn expected_response_ex_size(self) -> usize {
         match self {
             Self::ReadOCR => mem::size_of::<response::R3>(),
+            Self::SDStatus => 1, // R2
             _ => 0,
         }
     }
@@ -60,15 +114,25 @@ impl AppCommand {
 
 #[repr(u8)]
 #[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[allow(clippy::enum_variant_names)]
 pub enum Command {
     GoIdleState,
     SendIfCond(SendInterfaceCondition),
     SendCSD(RCA),
     StopTransmission,
+    SendStatus(RCA),
+    SetBlockLength(u32),
+    SetBlockCount(u32),
     ReadSingleBlock(Address),
     ReadMultipleBlock(Address),
     WriteBlock(Address),
     WriteMultipleBlock(Address),
+    ProgramCSD,
+    SetWriteProtect(Address),
+    ClearWriteProtect(Address),
+    SendWriteProtect(Address),
+    LockUnlock,
     AppCommand(RCA),
     App(AppCommand),
 }
@@ -80,10 +144,18 @@ impl Command {
             Self::SendIfCond(_) => 8,
             Self::SendCSD(_) => 9,
             Self::StopTransmission => 12,
+            Self::SendStatus(_) => 13,
+            Self::SetBlockLength(_) => 16,
+            Self::SetBlockCount(_) => 23,
             Self::ReadSingleBlock(_) => 17,
             Self::ReadMultipleBlock(_) => 18,
             Self::WriteBlock(_) => 24,
             Self::WriteMultipleBlock(_) => 25,
+            Self::ProgramCSD => 27,
+            Self::SetWriteProtect(_) => 28,
+            Self::ClearWriteProtect(_) => 29,
+            Self::SendWriteProtect(_) => 30,
+            Self::LockUnlock => 42,
             Self::AppCommand(_) => 55,
             Self::App(command) => command.index(),
         }
@@ -91,13 +163,19 @@ impl Command {
 
     pub fn argument(self) -> u32 {
         match self {
-            Self::GoIdleState | Self::StopTransmission => 0,
+            Self::GoIdleState | Self::StopTransmission | Self::ProgramCSD | Self::LockUnlock => 0,
             Self::SendIfCond(cond) => cond.into(),
-            Self::SendCSD(rca) | Self::AppCommand(rca) => (rca as u32) << 16,
+            Self::SendCSD(rca) | Self::SendStatus(rca) | Self::AppCommand(rca) => {
+                (rca as u32) << 16
+            }
+            Self::SetBlockLength(length) | Self::SetBlockCount(length) => length,
             Self::ReadSingleBlock(address)
             | Self::ReadMultipleBlock(address)
             | Self::WriteBlock(address)
-            | Self::WriteMultipleBlock(address) => address,
+            | Self::WriteMultipleBlock(address)
+            | Self::SetWriteProtect(address)
+            | Self::ClearWriteProtect(address)
+            | Self::SendWriteProtect(address) => address,
             Self::App(command) => command.argument(),
         }
     }
@@ -106,13 +184,15 @@ impl Command {
         match self {
             Self::SendIfCond(_) => mem::size_of::<response::R7>(),
             Self::WriteBlock(_) | Self::WriteMultipleBlock(_) => 1,
+            Self::SendStatus(_) => mem::size_of::<response::R2>(),
             Self::App(app_command) => app_command.expected_response_ex_size(),
             _ => 0,
         }
     }
 }
 
-fn crc7(data: &[u8]) -> u8 {
+/// Shifted left with end bit set, as last byte of command or CSD
+pub(crate) fn crc7(data: &[u8]) -> u8 {
     let mut crc = 0u8;
     for &b in data.iter() {
         for i in 0..8 {
@@ -125,10 +205,10 @@ fn crc7(data: &[u8]) -> u8 {
     crc << 1 | 1
 }
 
-impl Into<[u8; 6]> for Command {
-    fn into(self) -> [u8; 6] {
-        let bytes = u32::to_be_bytes(self.argument());
-        let mut buffer = [0x40 | self.index(), bytes[0], bytes[1], bytes[2], bytes[3], 0];
+impl From<Command> for [u8; 6] {
+    fn from(cmd: Command) -> [u8; 6] {
+        let bytes = u32::to_be_bytes(cmd.argument());
+        let mut buffer = [0x40 | cmd.index(), bytes[0], bytes[1], bytes[2], bytes[3], 0];
         buffer[5] = crc7(&buffer[..5]);
         buffer
     }
@@ -156,4 +236,19 @@ mod test {
         let bytes: [u8; 6] = cmd.into();
         assert_eq!(bytes, hex!("51 00 00 00 00 55"));
     }
+
+    #[test]
+    fn test_lock_unlock_data() {
+        use super::LockUnlock;
+
+        let mut buffer = [0u8; 34];
+        let command = LockUnlock::SetPassword { old: b"ab", new: b"xyz" };
+        let length = command.encode(&mut buffer).unwrap();
+        assert_eq!(buffer[..length], *b"\x01\x05abxyz");
+        let length = LockUnlock::Unlock(b"xyz").encode(&mut buffer).unwrap();
+        assert_eq!(buffer[..length], *b"\x00\x03xyz");
+        assert_eq!(LockUnlock::ForceErase.encode(&mut buffer), Some(1));
+        assert_eq!(buffer[0], 0x08);
+        assert_eq!(LockUnlock::Unlock(&[0; 17]).encode(&mut buffer), None);
+    }
 }
@@ -6,6 +6,7 @@ pub mod transfer;
 pub const BLOCK_SIZE: usize = 512;
 
 #[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Card {
     SDSC(u8),
     SDHC,
@@ -13,9 +14,6 @@ pub enum Card {
 
 impl Card {
     pub fn high_capacity(self) -> bool {
-        match self {
-            Self::SDSC(_) => false,
-            _ => true,
-        }
+        !matches!(self, Self::SDSC(_))
     }
 }
@@ -1,15 +1,23 @@
-use bitfield::bitfield;
+use bitfield::{bitfield, Bit};
+
+use super::command::crc7;
 
 bitfield! {
     #[derive(Copy, Clone)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
     pub struct CSDv1(u128);
     pub version, _: 127, 126;
     pub max_read_data_block_length, _: 83, 80;
     pub device_size, _: 73, 62;
     pub device_size_multiplier, _: 49, 47;
+    pub erase_sector_size, _: 45, 39;
+    pub write_protect_group_size, _: 38, 32;
+    pub write_protect_group_enable, _: 31;
+    pub max_write_data_block_length, _: 25, 22;
 }
 
 #[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct NumBlocks {
     device_size: u32,
     multiplier: u16,
@@ -25,9 +33,9 @@ impl NumBlocks {
     }
 }
 
-impl Into<u64> for NumBlocks {
-    fn into(self) -> u64 {
-        self.device_size as u64 * self.multiplier as u64
+impl From<NumBlocks> for u64 {
+    fn from(num_blocks: NumBlocks) -> u64 {
+        num_blocks.device_size as u64 * num_blocks.multiplier as u64
     }
 }
 
@@ -40,10 +48,24 @@ impl CSDv1 {
     pub fn read_block_size_shift(&self) -> u8 {
         self.max_read_data_block_length() as u8
     }
+
+    /// In bytes
+    pub fn erase_sector_bytes(&self) -> u32 {
+        (self.erase_sector_size() as u32 + 1) << self.max_write_data_block_length()
+    }
+
+    /// In erase sectors
+    pub fn write_protect_group_sectors(&self) -> Option<u32> {
+        match self.write_protect_group_enable() {
+            true => Some(self.write_protect_group_size() as u32 + 1),
+            false => None,
+        }
+    }
 }
 
 bitfield! {
     #[derive(Copy, Clone)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
     pub struct CSDv2(u128);
     pub device_size, _: 69, 48;
 }
@@ -56,6 +78,7 @@ impl CSDv2 {
 
 bitfield! {
     #[derive(Copy, Clone)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
     pub struct CSDv3(u128);
     pub device_size, _: 75, 48;
 }
@@ -67,6 +90,7 @@ impl CSDv3 {
 }
 
 #[derive(Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum CSD {
     V1(CSDv1),
     V2(CSDv2),
@@ -98,4 +122,174 @@ impl CSD {
             _ => 9, // 512 bytes
         }
     }
+
+    /// In bytes
+    pub fn capacity(&self) -> u64 {
+        u64::from(self.num_blocks()) << self.block_size_shift()
+    }
+
+    /// Erase sector in bytes, fixed to 64KB since CSD v2
+    pub fn erase_sector_bytes(&self) -> u32 {
+        match self {
+            Self::V1(csd) => csd.erase_sector_bytes(),
+            _ => 64 * 1024,
+        }
+    }
+
+    /// Write protect group in bytes, `None` if not supported as for SDHC and above
+    pub fn write_protect_group_bytes(&self) -> Option<u32> {
+        match self {
+            Self::V1(csd) => {
+                csd.write_protect_group_sectors().map(|n| n * csd.erase_sector_bytes())
+            }
+            _ => None,
+        }
+    }
+
+    pub fn raw(&self) -> u128 {
+        match self {
+            Self::V1(csd) => csd.0,
+            Self::V2(csd) => csd.0,
+            Self::V3(csd) => csd.0,
+        }
+    }
+
+    pub fn copy(&self) -> bool {
+        self.raw().bit(14)
+    }
+
+    pub fn permanent_write_protect(&self) -> bool {
+        self.raw().bit(13)
+    }
+
+    pub fn temporary_write_protect(&self) -> bool {
+        self.raw().bit(12)
+    }
+
+    /// With COPY and TMP_WRITE_PROTECT bits changed and CRC updated, for PROGRAM_CSD
+    pub fn with_flags(&self, copy: bool, temporary_write_protect: bool) -> Self {
+        let mut value = self.raw();
+        value.set_bit(14, copy);
+        value.set_bit(12, temporary_write_protect);
+        let mut bytes = value.to_be_bytes();
+        bytes[15] = crc7(&bytes[..15]);
+        Self::try_from(u128::from_be_bytes(bytes)).unwrap_or(*self)
+    }
+}
+
+bitfield! {
+    #[derive(Copy, Clone, Default)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    pub struct SCR(u64);
+    impl Debug;
+    pub structure, _: 63, 60;
+    pub sd_spec, _: 59, 56;
+    pub bus_widths, _: 51, 48;
+    pub sd_spec3, _: 47;
+    pub command_support, _: 35, 32;
+}
+
+impl SCR {
+    pub fn new(value: u64) -> Self {
+        Self(value)
+    }
+
+    /// CMD23 SET_BLOCK_COUNT
+    pub fn set_block_count_supported(&self) -> bool {
+        self.command_support() & 0b0010 != 0
+    }
+}
+
+/// 512 bits of SD Status, most significant byte first
+#[derive(Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SDStatus(pub [u8; 64]);
+
+impl SDStatus {
+    fn bits(&self, msb: usize, lsb: usize) -> u32 {
+        (lsb..=msb).rev().fold(0, |value, bit| {
+            let byte = self.0[63 - bit / 8];
+            value << 1 | ((byte >> (bit % 8)) & 1) as u32
+        })
+    }
+
+    pub fn speed_class(&self) -> u8 {
+        self.bits(447, 440) as u8
+    }
+
+    pub fn au_size(&self) -> u8 {
+        self.bits(431, 428) as u8
+    }
+
+    /// Allocation unit in bytes, `None` if not defined
+    pub fn au_bytes(&self) -> Option<u32> {
+        let kb = match self.au_size() {
+            0 => return None,
+            size @ 1..=0xA => 8 << size,
+            0xB => 12 * 1024,
+            0xC => 16 * 1024,
+            0xD => 24 * 1024,
+            0xE => 32 * 1024,
+            _ => 64 * 1024,
+        };
+        Some(kb * 1024)
+    }
+
+    /// Number of AUs erased at a time
+    pub fn erase_size(&self) -> u16 {
+        self.bits(423, 408) as u16
+    }
+
+    /// In seconds
+    pub fn erase_timeout(&self) -> u8 {
+        self.bits(407, 402) as u8
+    }
+
+    pub fn erase_offset(&self) -> u8 {
+        self.bits(401, 400) as u8
+    }
+}
+
+impl core::fmt::Debug for SDStatus {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("SDStatus")
+            .field("speed_class", &self.speed_class())
+            .field("au_size", &self.au_size())
+            .field("erase_size", &self.erase_size())
+            .field("erase_timeout", &self.erase_timeout())
+            .field("erase_offset", &self.erase_offset())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_csd_flags() {
+        use super::CSD;
+        use hex_literal::hex;
+
+        // CRC7 precomputed by polynomial division, not with crate::sd::command::crc7
+        let original = hex!("400E0032 5B590000 3B377F80 0A400067");
+        let flagged = hex!("400E0032 5B590000 3B377F80 0A40509D");
+        let csd = CSD::try_from(u128::from_be_bytes(original)).unwrap();
+        assert!(!csd.copy() && !csd.temporary_write_protect());
+        let csd = csd.with_flags(true, true);
+        assert!(csd.copy() && csd.temporary_write_protect() && !csd.permanent_write_protect());
+        assert_eq!(csd.raw().to_be_bytes(), flagged);
+        assert_eq!(csd.with_flags(false, false).raw().to_be_bytes(), original);
+        assert_eq!(csd.write_protect_group_bytes(), None);
+    }
+
+    #[test]
+    fn test_scr_command_support() {
+        use super::SCR;
+
+        assert!(!SCR::default().set_block_count_supported());
+        assert!(!SCR::new(1 << 32).set_block_count_supported());
+        assert!(SCR::new(1 << 33).set_block_count_supported());
+        // SCR of SDHC card supporting CMD23 and CMD20
+        let scr = SCR::new(0x0235_8003_0000_0000);
+        assert!(scr.set_block_count_supported() && scr.sd_spec() == 2);
+    }
 }
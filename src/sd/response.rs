@@ -1,7 +1,9 @@
 use bitfield::Bit;
 use displaydoc::Display;
 
-#[derive(Copy, Clone, Debug)]
+/// R1 flags, also the first byte of other responses
+#[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[repr(C)]
 pub struct R1(pub u8);
 
@@ -11,11 +13,14 @@ impl Default for R1 {
     }
 }
 
-#[derive(Copy, Clone, Debug, Display)]
+#[derive(Copy, Clone, Debug, Display, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[repr(u8)]
 pub enum R1Status {
     /// in idle state
     InIdleState = 0,
+    /// erase reset
+    EraseReset,
     /// illegal command
     IllegalCommand,
     /// command CRC error
@@ -28,9 +33,24 @@ pub enum R1Status {
     ParameterError,
 }
 
+impl R1Status {
+    pub const ALL: [Self; 7] = [
+        Self::InIdleState,
+        Self::EraseReset,
+        Self::IllegalCommand,
+        Self::CommandCRCError,
+        Self::EraseSequenceError,
+        Self::AddressError,
+        Self::ParameterError,
+    ];
+}
+
 impl core::error::Error for R1Status {}
 
 impl R1 {
+    /// All flags except in idle state and erase reset, which are status only
+    const ERRORS: u8 = 0b111_1100;
+
     pub fn valid(self) -> bool {
         !self.0.bit(7)
     }
@@ -39,21 +59,79 @@ impl R1 {
         self.0.bit(status as usize)
     }
 
-    pub fn error(self) -> Option<R1Status> {
-        let value = (self.0 >> 3) & 0b1111;
-        let error_bit = value ^ ((value.wrapping_sub(1)) & value);
-        let error = match error_bit {
-            0b0001 => R1Status::CommandCRCError,
-            0b0010 => R1Status::EraseSequenceError,
-            0b0100 => R1Status::AddressError,
-            0b1000 => R1Status::ParameterError,
-            _ => return None,
-        };
-        Some(error)
+    pub fn flags(self) -> impl Iterator<Item = R1Status> {
+        R1Status::ALL.into_iter().filter(move |&status| self.has(status))
+    }
+
+    /// Whole response if any error flag set
+    pub fn error(self) -> Option<R1> {
+        (self.0 & Self::ERRORS != 0).then_some(self)
+    }
+}
+
+impl core::fmt::Debug for R1 {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_set().entries(self.flags()).finish()
+    }
+}
+
+impl core::fmt::Display for R1 {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for (i, status) in self.flags().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", status)?;
+        }
+        Ok(())
+    }
+}
+
+impl core::error::Error for R1 {}
+
+/// Second byte of R2, following R1
+#[derive(Copy, Clone, Default, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(C)]
+pub struct R2(pub u8);
+
+impl R2 {
+    pub fn card_locked(self) -> bool {
+        self.0.bit(0)
+    }
+
+    /// Write protect erase skip, or lock/unlock command failed
+    pub fn lock_unlock_failed(self) -> bool {
+        self.0.bit(1)
+    }
+
+    pub fn error(self) -> bool {
+        self.0.bit(2)
+    }
+
+    pub fn card_controller_error(self) -> bool {
+        self.0.bit(3)
+    }
+
+    pub fn card_ecc_failed(self) -> bool {
+        self.0.bit(4)
+    }
+
+    pub fn write_protect_violation(self) -> bool {
+        self.0.bit(5)
+    }
+
+    pub fn erase_parameter(self) -> bool {
+        self.0.bit(6)
+    }
+
+    pub fn out_of_range(self) -> bool {
+        self.0.bit(7)
     }
 }
 
 #[derive(Copy, Clone, Default, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[repr(C)]
 pub struct R3(pub u32);
 
@@ -64,6 +142,7 @@ impl R3 {
 }
 
 #[derive(Copy, Clone, Default, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct R7(pub u32);
 
 impl R7 {
@@ -81,3 +160,19 @@ pub struct Response {
     pub r1: R1,
     pub ex: u32,
 }
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_r1_flags() {
+        use super::{R1Status, R1};
+
+        assert!(R1(0x01).error().is_none());
+        // Command ending aborted erase sequence succeeds with erase reset
+        assert!(R1(0x02).error().is_none() && R1(0x02).has(R1Status::EraseReset));
+        let r1 = R1(0x05);
+        assert!(r1.has(R1Status::IllegalCommand) && !r1.has(R1Status::EraseReset));
+        let r1 = R1(0x62).error().unwrap();
+        assert_eq!(r1.to_string(), "erase reset, address error, parameter error");
+    }
+}
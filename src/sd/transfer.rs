@@ -1,6 +1,7 @@
 use displaydoc::Display;
 
 #[derive(Copy, Clone, Debug, Display)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum TokenError {
     /// not token
     NotToken,
@@ -19,6 +20,7 @@ pub enum TokenError {
 impl core::error::Error for TokenError {}
 
 #[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[repr(u8)]
 pub enum Token {
     Start = 0xFE,
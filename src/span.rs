@@ -0,0 +1,53 @@
+//! Spans of `tracing` feature, instrumenting futures in async mode
+//! and entered around the call in blocking mode
+
+#[cfg(feature = "tracing")]
+use std::time::Instant;
+
+#[cfg(feature = "tracing")]
+use crate::bus::Error;
+
+/// Records duration in microseconds and outcome of operation in its span
+#[cfg(feature = "tracing")]
+pub(crate) fn record<T, E>(span: &tracing::Span, start: Instant, result: &Result<T, Error<E>>) {
+    span.record("duration_us", start.elapsed().as_micros() as u64);
+    match result {
+        Ok(_) => span.record("outcome", "ok"),
+        Err(error) => span.record("outcome", error.kind().name()),
+    };
+}
+
+/// `traced!(span, call)` evaluates to result of `call`, awaited in async mode
+#[cfg(all(feature = "tracing", feature = "async"))]
+macro_rules! traced {
+    ($span:expr, $call:expr) => {{
+        let (span, start) = ($span, std::time::Instant::now());
+        let result = tracing::Instrument::instrument($call, span.clone()).await;
+        $crate::span::record(&span, start, &result);
+        result
+    }};
+}
+
+#[cfg(all(feature = "tracing", not(feature = "async")))]
+macro_rules! traced {
+    ($span:expr, $call:expr) => {{
+        let (span, start) = ($span, std::time::Instant::now());
+        let result = span.in_scope(|| $call);
+        $crate::span::record(&span, start, &result);
+        result
+    }};
+}
+
+#[cfg(all(not(feature = "tracing"), feature = "async"))]
+macro_rules! traced {
+    ($span:expr, $call:expr) => {
+        $call.await
+    };
+}
+
+#[cfg(all(not(feature = "tracing"), not(feature = "async")))]
+macro_rules! traced {
+    ($span:expr, $call:expr) => {
+        $call
+    };
+}
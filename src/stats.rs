@@ -0,0 +1,178 @@
+//! I/O statistics kept by [`SD`](crate::SD), timed with the clock of the bus
+
+use core::time::Duration;
+
+use crate::bus::{Activity, Error, ErrorKind, Phase};
+use crate::sd::response::R1Status;
+
+/// Latency distribution of one kind of operation, successful operations only
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Latency {
+    count: u32,
+    total: Duration,
+    min: Duration,
+    max: Duration,
+    histogram: [u32; Latency::BUCKETS],
+}
+
+impl Latency {
+    pub const BUCKETS: usize = 16;
+
+    pub(crate) fn record(&mut self, latency: Duration) {
+        self.min = if self.count == 0 { latency } else { self.min.min(latency) };
+        self.max = self.max.max(latency);
+        self.count += 1;
+        self.total += latency;
+        let micros = (latency.as_micros() >> 6) as u64;
+        let bucket = (u64::BITS - micros.leading_zeros()) as usize;
+        self.histogram[bucket.min(Self::BUCKETS - 1)] += 1;
+    }
+
+    /// Records latency between two uptimes of bus, nothing if bus has no clock
+    pub(crate) fn record_since(&mut self, start: Option<Duration>, end: Option<Duration>) {
+        if let (Some(start), Some(end)) = (start, end) {
+            self.record(end.saturating_sub(start));
+        }
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn min(&self) -> Option<Duration> {
+        (self.count > 0).then_some(self.min)
+    }
+
+    pub fn max(&self) -> Option<Duration> {
+        (self.count > 0).then_some(self.max)
+    }
+
+    pub fn average(&self) -> Option<Duration> {
+        (self.count > 0).then(|| self.total / self.count)
+    }
+
+    /// Bucket 0 counts latencies below 64us, each following bucket up to
+    /// twice the limit of the previous one, the last bucket being unbounded
+    pub fn histogram(&self) -> &[u32; Self::BUCKETS] {
+        &self.histogram
+    }
+
+    /// Exclusive upper limit of histogram `bucket`, `None` for the last one
+    pub fn bucket_limit(bucket: usize) -> Option<Duration> {
+        (bucket < Self::BUCKETS - 1).then(|| Duration::from_micros(64 << bucket))
+    }
+}
+
+/// Timeouts by the step where card failed to answer in time
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Timeouts {
+    /// No response to a command
+    pub response: u32,
+    /// No data token where data block expected
+    pub data_token: u32,
+    /// Card still busy after programming or stop
+    pub busy: u32,
+    /// Card still idle after initialization
+    pub init: u32,
+}
+
+/// Counters since creation of [`SD`](crate::SD) or last reset
+///
+/// Force erase through [`SD::lock_unlock`](crate::SD::lock_unlock) is the only
+/// erase this crate issues, hence the only one timed in `erase`. Latencies stay
+/// empty and `busy` zero with a bus not implementing
+/// [`uptime`](crate::bus::Bus::uptime) and [`take_activity`](crate::bus::Bus::take_activity).
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Statistics {
+    pub commands: u32,
+    pub blocks_read: u64,
+    pub blocks_written: u64,
+    /// In place of retries, which this crate leaves to the caller: failures after
+    /// which the card was brought back to transfer state, ready for a retry
+    pub recoveries: u32,
+    /// Commands and data blocks rejected for CRC mismatch
+    pub crc_errors: u32,
+    pub timeouts: Timeouts,
+    /// Time spent waiting while card busy
+    pub busy: Duration,
+    pub read: Latency,
+    pub write: Latency,
+    pub erase: Latency,
+}
+
+impl Statistics {
+    pub(crate) fn add(&mut self, activity: Activity) {
+        self.commands += activity.commands;
+        self.busy += activity.busy;
+    }
+
+    /// Counts failure of `result`, if any
+    pub(crate) fn count<T, E>(&mut self, result: &Result<T, Error<E>>) {
+        let error = match result {
+            Ok(_) => return,
+            Err(error) => error,
+        };
+        if error.status().is_some() {
+            self.recoveries += 1;
+        }
+        match (error.kind(), error.phase()) {
+            (ErrorKind::CRC, _) => self.crc_errors += 1,
+            (ErrorKind::Command(r1), _) if r1.has(R1Status::CommandCRCError) => {
+                self.crc_errors += 1
+            }
+            (ErrorKind::NoResponse, _) => self.timeouts.response += 1,
+            (ErrorKind::InitTimeout, _) => self.timeouts.init += 1,
+            (ErrorKind::Timeout, Some(Phase::Busy)) => self.timeouts.busy += 1,
+            (ErrorKind::Timeout, _) => self.timeouts.data_token += 1,
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_latency() {
+        use core::time::Duration;
+
+        use super::Latency;
+
+        let mut latency = Latency::default();
+        latency.record_since(None, Some(Duration::from_secs(1)));
+        assert_eq!(latency.average(), None);
+        for micros in [10, 64, 100, 1000, 10_000_000] {
+            latency.record(Duration::from_micros(micros));
+        }
+        assert_eq!(latency.min(), Some(Duration::from_micros(10)));
+        assert_eq!(latency.max(), Some(Duration::from_secs(10)));
+        assert_eq!(latency.average(), Some(Duration::from_nanos(2_000_234_800)));
+        let mut expected = [0; Latency::BUCKETS];
+        expected[0] = 1;
+        expected[1] = 2;
+        expected[4] = 1;
+        expected[Latency::BUCKETS - 1] = 1;
+        assert_eq!(latency.histogram(), &expected);
+        assert_eq!(Latency::bucket_limit(4), Some(Duration::from_micros(1024)));
+        assert_eq!(Latency::bucket_limit(Latency::BUCKETS - 1), None);
+    }
+
+    #[test]
+    fn test_count_errors() {
+        use super::Statistics;
+        use crate::bus::{Error, ErrorKind, Phase};
+        use crate::sd::response::{R1, R2};
+
+        let mut statistics = Statistics::default();
+        statistics.count::<(), ()>(&Err(Error::from(ErrorKind::Timeout).at(Phase::Busy)));
+        statistics.count::<(), ()>(&Err(Error::from(ErrorKind::Timeout).at(Phase::DataToken)));
+        statistics.count::<(), ()>(&Err(Error::from(R1(1 << 3)).with_status(Some(R2(0)))));
+        statistics.count::<(), ()>(&Err(ErrorKind::NoResponse.into()));
+        statistics.count::<(), ()>(&Ok(()));
+        assert_eq!((statistics.timeouts.busy, statistics.timeouts.data_token), (1, 1));
+        assert_eq!(statistics.timeouts.response, 1);
+        assert_eq!((statistics.crc_errors, statistics.recoveries), (1, 1));
+    }
+}
@@ -0,0 +1,211 @@
+use core::{cmp::min, iter, ops::Range, slice};
+
+use derive_more::Display;
+use thiserror::Error;
+
+use crate::{block::Device, sd::BLOCK_SIZE};
+
+#[derive(Debug, Error, Display)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<E> {
+    #[display("{_0}")]
+    Device(E),
+    /// Seek before start of stream, or beyond `u64::MAX`
+    #[display("invalid seek")]
+    InvalidSeek,
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(error: E) -> Self {
+        Self::Device(error)
+    }
+}
+
+/// Byte stream over a range of a block device
+///
+/// Unaligned head and tail of an access go through a one block buffer with
+/// read-modify-write, a modified buffer is written back when another block is
+/// buffered or on `flush`, so `flush` before dropping the stream.
+pub struct Stream<D> {
+    device: D,
+    range: Range<u64>,
+    position: u64,
+    buffer: [u8; BLOCK_SIZE],
+    buffered: Option<u32>,
+    dirty: bool,
+}
+
+impl<D: Device> Stream<D> {
+    pub fn new(device: D) -> Self {
+        let size = device.num_blocks() as u64 * BLOCK_SIZE as u64;
+        Self::with_range(device, 0..size)
+    }
+
+    /// `range` in bytes, clamped to size of device
+    pub fn with_range(device: D, range: Range<u64>) -> Self {
+        let size = device.num_blocks() as u64 * BLOCK_SIZE as u64;
+        let range = min(range.start, size)..min(range.end, size);
+        Self { device, range, position: 0, buffer: [0; BLOCK_SIZE], buffered: None, dirty: false }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.range.end.saturating_sub(self.range.start)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Position beyond end is allowed, reads and writes there simply return 0
+    pub fn set_position(&mut self, position: u64) {
+        self.position = position;
+    }
+
+    /// Seek to `base` + `offset`
+    pub fn seek_from(&mut self, base: u64, offset: i64) -> Result<u64, Error<D::Error>> {
+        let position = base.checked_add_signed(offset).ok_or(Error::InvalidSeek)?;
+        self.position = position;
+        Ok(position)
+    }
+
+    pub fn into_inner(self) -> D {
+        self.device
+    }
+
+    /// Block address and offset within block of current position,
+    /// and number of bytes available up to `size`
+    fn locate(&self, size: usize) -> (u32, usize, usize) {
+        let offset = self.range.start.saturating_add(self.position);
+        let available = min(size as u64, self.range.end.saturating_sub(offset)) as usize;
+        ((offset / BLOCK_SIZE as u64) as u32, (offset % BLOCK_SIZE as u64) as usize, available)
+    }
+
+    fn buffered_within(&self, address: u32, num_blocks: usize) -> bool {
+        self.buffered.is_some_and(|buffered| buffered.wrapping_sub(address) < num_blocks as u32)
+    }
+}
+
+#[cfg_attr(not(feature = "async"), deasync::deasync)]
+impl<D: Device> Stream<D> {
+    async fn write_back(&mut self) -> Result<(), D::Error> {
+        if let (Some(address), true) = (self.buffered, self.dirty) {
+            self.device.write(address, iter::once(&self.buffer)).await?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+
+    async fn load(&mut self, address: u32) -> Result<(), D::Error> {
+        if self.buffered == Some(address) {
+            return Ok(());
+        }
+        self.write_back().await?;
+        self.buffered = None;
+        self.device.read(address, slice::from_mut(&mut self.buffer).iter_mut()).await?;
+        self.buffered = Some(address);
+        Ok(())
+    }
+
+    pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error<D::Error>> {
+        let (address, skip, size) = self.locate(buf.len());
+        if size == 0 {
+            return Ok(0);
+        }
+        if skip == 0 && size >= BLOCK_SIZE {
+            let blocks = &mut buf.as_chunks_mut::<BLOCK_SIZE>().0[..size / BLOCK_SIZE];
+            if self.buffered_within(address, blocks.len()) {
+                self.write_back().await?;
+            }
+            self.device.read(address, blocks.iter_mut()).await?;
+            let size = blocks.len() * BLOCK_SIZE;
+            self.position += size as u64;
+            return Ok(size);
+        }
+        self.load(address).await?;
+        let size = min(size, BLOCK_SIZE - skip);
+        buf[..size].copy_from_slice(&self.buffer[skip..skip + size]);
+        self.position += size as u64;
+        Ok(size)
+    }
+
+    pub async fn write(&mut self, buf: &[u8]) -> Result<usize, Error<D::Error>> {
+        let (address, skip, size) = self.locate(buf.len());
+        if size == 0 {
+            return Ok(0);
+        }
+        if skip == 0 && size >= BLOCK_SIZE {
+            let blocks = &buf.as_chunks::<BLOCK_SIZE>().0[..size / BLOCK_SIZE];
+            self.device.write(address, blocks.iter()).await?;
+            // Buffered block overwritten, kept if write failed to not lose its changes
+            if self.buffered_within(address, blocks.len()) {
+                (self.buffered, self.dirty) = (None, false);
+            }
+            let size = blocks.len() * BLOCK_SIZE;
+            self.position += size as u64;
+            return Ok(size);
+        }
+        self.load(address).await?;
+        let size = min(size, BLOCK_SIZE - skip);
+        self.buffer[skip..skip + size].copy_from_slice(&buf[..size]);
+        self.dirty = true;
+        self.position += size as u64;
+        Ok(size)
+    }
+
+    pub async fn flush(&mut self) -> Result<(), Error<D::Error>> {
+        Ok(self.write_back().await?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_unaligned_read_write() {
+        use super::Stream;
+        use crate::block::test::{block_on, Memory};
+
+        let mut memory = Memory::<4>::default();
+        let mut stream = Stream::with_range(&mut memory, 100..1900);
+        assert_eq!(stream.len(), 1800);
+        let data: [u8; 1800] = core::array::from_fn(|i| i as u8);
+        let mut written = 0;
+        while written < data.len() {
+            written += block_on(stream.write(&data[written..])).unwrap();
+        }
+        assert_eq!(block_on(stream.write(&data)).unwrap(), 0);
+        block_on(stream.flush()).unwrap();
+
+        let mut buffer = [0u8; 1800];
+        stream.set_position(0);
+        let mut read = 0;
+        while read < buffer.len() {
+            read += block_on(stream.read(&mut buffer[read..])).unwrap();
+        }
+        assert_eq!(buffer, data);
+
+        let memory = stream.into_inner();
+        assert_eq!(memory.blocks[0][..100], [0u8; 100]);
+        assert_eq!(memory.blocks[0][100..], data[..412]);
+        assert_eq!(memory.blocks[3][..364], data[1436..]);
+        assert_eq!(memory.blocks[3][364..], [0u8; 148]);
+    }
+    #[test]
+    fn test_failed_aligned_write() {
+        use super::Stream;
+        use crate::block::test::{block_on, Memory};
+
+        let mut stream = Stream::new(Memory::<4>::default());
+        assert_eq!(block_on(stream.write(&[1; 100])).unwrap(), 100);
+        stream.device.read_only = true;
+        stream.set_position(0);
+        assert!(block_on(stream.write(&[2; 1024])).is_err());
+        stream.device.read_only = false;
+        block_on(stream.flush()).unwrap();
+        let memory = stream.into_inner();
+        assert_eq!(memory.blocks[0][..100], [1; 100]);
+    }
+}